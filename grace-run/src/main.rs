@@ -0,0 +1,171 @@
+//! # grace-run
+//!
+//! Minimal init process built on `grace`. Runs a command as a child
+//! process, forwards signals to it, reaps zombies, and exits with the
+//! child's status.
+
+#[cfg(not(windows))]
+mod unix {
+    use std::env;
+    use std::ffi::OsString;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::{self, Command, ExitStatus};
+    use std::time::Duration;
+
+    use grace::{ChildReaper, ForwardGuard, ForwardOptions, ShutdownType};
+
+    // Signals which are forwarded in addition to interrupt and terminate requests
+    const FORWARDED_SIGNALS: &[libc::c_int] = &[
+        libc::SIGHUP,
+        libc::SIGQUIT,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+        libc::SIGWINCH,
+    ];
+
+    const USAGE: &str = "usage: grace-run [-s] [-g] [-t SECONDS] [--] COMMAND [ARGS...]";
+
+    struct Args {
+        subreaper: bool,
+        process_group: bool,
+        kill_after: Option<Duration>,
+        command: Vec<OsString>,
+    }
+
+    fn usage() -> ! {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+
+    fn parse_args() -> Args {
+        let mut args = Args {
+            subreaper: false,
+            process_group: false,
+            kill_after: None,
+            command: Vec::new(),
+        };
+        let mut iter = env::args_os().skip(1);
+        while let Some(arg) = iter.next() {
+            match arg.to_str() {
+                Some("-s") => args.subreaper = true,
+                Some("-g") => args.process_group = true,
+                Some("-t") => {
+                    let secs = iter
+                        .next()
+                        .and_then(|s| s.to_str()?.parse::<f64>().ok())
+                        .filter(|&secs| secs >= 0.0)
+                        .unwrap_or_else(|| usage());
+                    args.kill_after = Some(Duration::from_secs_f64(secs));
+                }
+                Some("-h") | Some("--help") => {
+                    println!("{}", USAGE);
+                    process::exit(0);
+                }
+                Some("--") => {
+                    args.command.extend(iter);
+                    break;
+                }
+                Some(s) if s.starts_with('-') => usage(),
+                _ => {
+                    args.command.push(arg);
+                    args.command.extend(iter);
+                    break;
+                }
+            }
+        }
+        if args.command.is_empty() {
+            usage();
+        }
+        args
+    }
+
+    fn fail(context: &str, e: impl std::fmt::Display) -> ! {
+        eprintln!("grace-run: {}: {}", context, e);
+        process::exit(1);
+    }
+
+    // Exit in the same way as the child, re-raising its terminating signal.
+    fn exit_like(status: ExitStatus) -> ! {
+        if let Some(signum) = status.signal() {
+            unsafe {
+                libc::signal(signum, libc::SIG_DFL);
+                let mut set = std::mem::zeroed();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, signum);
+                libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+                libc::raise(signum);
+            }
+            // Signals with the default disposition are not delivered to PID 1
+            process::exit(128 + signum);
+        }
+        process::exit(status.code().unwrap_or(1));
+    }
+
+    pub fn main() {
+        let args = parse_args();
+
+        // The reaper must exist before the child is spawned, so that its
+        // exit status is retained even if it exits immediately.
+        let reaper = if args.subreaper {
+            #[cfg(target_os = "linux")]
+            {
+                ChildReaper::subreaper()
+            }
+            #[cfg(not(target_os = "linux"))]
+            {
+                fail("-s", "subreapers are only supported on linux")
+            }
+        } else {
+            ChildReaper::orphans()
+        }
+        .unwrap_or_else(|e| fail("failed to start reaper", e));
+
+        let mut command = Command::new(&args.command[0]);
+        command.args(&args.command[1..]);
+        if args.process_group {
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setpgid(0, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+        // The child is waited on by the reaper, rather than via `Child::wait`
+        let pid = command
+            .spawn()
+            .map(|child| child.id())
+            .unwrap_or_else(|e| fail(&args.command[0].to_string_lossy(), e));
+        let status = reaper.watch_channel(pid);
+
+        let mut options = ForwardOptions::new()
+            .types(&[ShutdownType::Interrupt, ShutdownType::Terminate])
+            .signals(FORWARDED_SIGNALS)
+            .process_group(args.process_group);
+        if let Some(timeout) = args.kill_after {
+            options = options.kill_after(timeout);
+        }
+        let forward = ForwardGuard::new(pid, &options)
+            .unwrap_or_else(|e| fail("failed to forward signals", e));
+
+        let status = status
+            .recv()
+            .unwrap_or_else(|e| fail("failed to wait for child", e));
+
+        drop(forward);
+        drop(reaper);
+        exit_like(status);
+    }
+}
+
+#[cfg(not(windows))]
+fn main() {
+    unix::main()
+}
+
+#[cfg(windows)]
+fn main() {
+    eprintln!("grace-run is not supported on this platform");
+    std::process::exit(1);
+}
@@ -0,0 +1,8 @@
+use signal_stack::CrashGuard;
+
+fn main() {
+    let _guard = CrashGuard::stderr().unwrap();
+    unsafe {
+        std::ptr::null_mut::<u8>().write_volatile(1);
+    }
+}
@@ -1,18 +1,25 @@
+use std::io;
 use std::mem;
 
 use libc::c_int;
 
+use super::{Disposition, Error, SignalOptions};
+
 pub trait SigHandler: Clone {
     type Data;
 
-    fn ours() -> Self;
+    fn ours(options: &SignalOptions) -> Self;
     unsafe fn delegate(&self, signum: c_int, data: Self::Data);
-    fn install(&self, signum: c_int) -> Self;
+    fn install(&self, signum: c_int) -> io::Result<Self>;
     fn detect(signum: c_int) -> Self;
+    fn validate(signum: c_int) -> Result<(), Error>;
+    fn is_default(&self) -> bool;
+    fn is_ours(&self) -> bool;
+    fn disposition(&self) -> Disposition;
 }
 
 use super::stack::our_handler;
-pub use handler_impl::PlatformSigHandler;
+pub use handler_impl::{current_thread, PlatformSigHandler};
 pub type PlatformSigData = <PlatformSigHandler as SigHandler>::Data;
 
 #[cfg(windows)]
@@ -22,14 +29,24 @@ mod handler_impl {
     const SIG_DFL: libc::sighandler_t = 0;
     const SIG_IGN: libc::sighandler_t = 1;
     const SIG_GET: libc::sighandler_t = 2;
+    const SIG_ERR: libc::sighandler_t = !0;
 
     type SigHandlerPtr = extern "C" fn(c_int);
 
+    extern "system" {
+        fn GetCurrentThreadId() -> u32;
+    }
+
+    // Identifies the calling thread. This is async-signal-safe.
+    pub fn current_thread() -> usize {
+        unsafe { GetCurrentThreadId() as usize }
+    }
+
     extern "C" fn handler_thunk(signum: c_int) {
         our_handler(signum, ());
 
         // Handler is uninstalled after each call, so reinstall it
-        PlatformSigHandler::ours().install(signum);
+        let _ = PlatformSigHandler::ours(&SignalOptions::default()).install(signum);
     }
 
     #[derive(Clone)]
@@ -38,7 +55,7 @@ mod handler_impl {
     impl SigHandler for PlatformSigHandler {
         type Data = ();
 
-        fn ours() -> Self {
+        fn ours(_options: &SignalOptions) -> Self {
             Self(unsafe { mem::transmute::<SigHandlerPtr, _>(handler_thunk) })
         }
 
@@ -54,13 +71,46 @@ mod handler_impl {
             }
         }
 
-        fn install(&self, signum: libc::c_int) -> Self {
-            Self(unsafe { libc::signal(signum, self.0) })
+        fn install(&self, signum: libc::c_int) -> io::Result<Self> {
+            match unsafe { libc::signal(signum, self.0) } {
+                SIG_ERR => Err(io::Error::last_os_error()),
+                prev => Ok(Self(prev)),
+            }
         }
 
         fn detect(signum: libc::c_int) -> Self {
             Self(unsafe { libc::signal(signum, SIG_GET) })
         }
+
+        fn validate(signum: libc::c_int) -> Result<(), Error> {
+            // These are the only signals supported by the windows CRT
+            match signum {
+                libc::SIGINT
+                | libc::SIGILL
+                | libc::SIGFPE
+                | libc::SIGSEGV
+                | libc::SIGTERM
+                | 21 // SIGBREAK
+                | libc::SIGABRT => Ok(()),
+                _ => Err(Error::InvalidSignal(signum)),
+            }
+        }
+
+        fn is_default(&self) -> bool {
+            self.0 == SIG_DFL
+        }
+
+        fn is_ours(&self) -> bool {
+            self.0 == handler_thunk as SigHandlerPtr as libc::sighandler_t
+        }
+
+        fn disposition(&self) -> Disposition {
+            match self.0 {
+                SIG_DFL => Disposition::Default,
+                SIG_IGN => Disposition::Ignore,
+                address => Disposition::Handler { address, flags: 0 },
+            }
+        }
     }
 }
 
@@ -72,6 +122,11 @@ mod handler_impl {
     type SigHandlerPtr = extern "C" fn(c_int);
     type SigActionPtr = extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void);
 
+    // Identifies the calling thread. This is async-signal-safe.
+    pub fn current_thread() -> usize {
+        unsafe { libc::pthread_self() as usize }
+    }
+
     extern "C" fn handler_thunk(signum: c_int, info: *mut libc::siginfo_t, ucontext: *mut c_void) {
         our_handler(signum, (info, ucontext));
     }
@@ -82,12 +137,28 @@ mod handler_impl {
     impl SigHandler for PlatformSigHandler {
         type Data = (*mut libc::siginfo_t, *mut c_void);
 
-        fn ours() -> Self {
+        fn ours(options: &SignalOptions) -> Self {
             Self(unsafe {
                 let mut res: libc::sigaction = mem::zeroed();
-                res.sa_sigaction = handler_thunk as libc::sighandler_t;
-                res.sa_flags = libc::SA_SIGINFO | libc::SA_NOCLDSTOP | libc::SA_RESTART;
-                libc::sigfillset(&mut res.sa_mask);
+                res.sa_sigaction = handler_thunk as SigActionPtr as libc::sighandler_t;
+                res.sa_flags = libc::SA_SIGINFO | libc::SA_NOCLDSTOP;
+                if options.restart_or_default() {
+                    res.sa_flags |= libc::SA_RESTART;
+                }
+                if options.on_stack_or_default() {
+                    res.sa_flags |= libc::SA_ONSTACK;
+                }
+                if options.no_defer_or_default() {
+                    res.sa_flags |= libc::SA_NODEFER;
+                }
+                if let Some(mask) = options.mask_or_default() {
+                    libc::sigemptyset(&mut res.sa_mask);
+                    for &signum in mask {
+                        libc::sigaddset(&mut res.sa_mask, signum);
+                    }
+                } else {
+                    libc::sigfillset(&mut res.sa_mask);
+                }
                 res
             })
         }
@@ -129,30 +200,39 @@ mod handler_impl {
                     | libc::SIGUSR2
                     | libc::SIGVTALRM => libc::_exit(3),
                     _ => {
-                        let prev = self.install(signum);
-                        libc::raise(signum);
-                        if prev.install(signum).0.sa_sigaction != self.0.sa_sigaction {
-                            // Uh oh... Race condition! Just set our signal handler again.
-                            Self::ours().install(signum);
+                        if let Ok(prev) = self.install(signum) {
+                            libc::raise(signum);
+                            if let Ok(replaced) = prev.install(signum) {
+                                if replaced.0.sa_sigaction != self.0.sa_sigaction {
+                                    // Uh oh... Race condition! Just set our signal handler again.
+                                    let _ = prev.install(signum);
+                                }
+                            }
                         }
                     }
                 }
             } else if self.0.sa_sigaction != libc::SIG_IGN {
                 // Non-default handler, call directly
                 if self.0.sa_flags & libc::SA_SIGINFO != 0 {
-                    mem::transmute::<_, SigActionPtr>(self.0.sa_sigaction)(signum, data.0, data.1);
+                    mem::transmute::<libc::sighandler_t, SigActionPtr>(self.0.sa_sigaction)(
+                        signum, data.0, data.1,
+                    );
                 } else {
-                    mem::transmute::<_, SigHandlerPtr>(self.0.sa_sigaction)(signum);
+                    mem::transmute::<libc::sighandler_t, SigHandlerPtr>(self.0.sa_sigaction)(
+                        signum,
+                    );
                 }
             }
         }
 
-        fn install(&self, signum: libc::c_int) -> Self {
-            Self(unsafe {
+        fn install(&self, signum: libc::c_int) -> io::Result<Self> {
+            unsafe {
                 let mut res = mem::zeroed();
-                libc::sigaction(signum, &self.0, &mut res);
-                res
-            })
+                if libc::sigaction(signum, &self.0, &mut res) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(Self(res))
+            }
         }
 
         fn detect(signum: libc::c_int) -> Self {
@@ -162,5 +242,40 @@ mod handler_impl {
                 res
             })
         }
+
+        fn validate(signum: libc::c_int) -> Result<(), Error> {
+            match signum {
+                libc::SIGKILL | libc::SIGSTOP => Err(Error::ForbiddenSignal(signum)),
+                _ => unsafe {
+                    // Let libc tell us whether this is a valid signal number
+                    let mut set = mem::zeroed();
+                    libc::sigemptyset(&mut set);
+                    if libc::sigaddset(&mut set, signum) == 0 {
+                        Ok(())
+                    } else {
+                        Err(Error::InvalidSignal(signum))
+                    }
+                },
+            }
+        }
+
+        fn is_default(&self) -> bool {
+            self.0.sa_sigaction == libc::SIG_DFL
+        }
+
+        fn is_ours(&self) -> bool {
+            self.0.sa_sigaction == handler_thunk as SigActionPtr as libc::sighandler_t
+        }
+
+        fn disposition(&self) -> Disposition {
+            match self.0.sa_sigaction {
+                libc::SIG_DFL => Disposition::Default,
+                libc::SIG_IGN => Disposition::Ignore,
+                address => Disposition::Handler {
+                    address,
+                    flags: self.0.sa_flags as c_int,
+                },
+            }
+        }
     }
 }
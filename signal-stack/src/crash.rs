@@ -0,0 +1,221 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use libc::{c_int, c_void};
+
+use super::backend::PlatformSigData;
+use super::writer::SignalWriter;
+use super::{Error, SignalHandlerGuard, SignalOptions};
+
+const CRASH_SIGNALS: &[c_int] = &[libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE];
+const ALT_STACK_SIZE: usize = 64 * 1024;
+
+static CRASH_FD: AtomicI32 = AtomicI32::new(-1);
+
+fn signal_name(signum: c_int) -> &'static str {
+    match signum {
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGILL => "SIGILL",
+        libc::SIGFPE => "SIGFPE",
+        _ => "unknown signal",
+    }
+}
+
+struct AltStack {
+    _memory: Box<[u8]>,
+    prev: libc::stack_t,
+}
+
+impl AltStack {
+    // Installs an alternate signal stack for the current thread, unless
+    // one has already been installed (eg. by the Rust standard library).
+    unsafe fn install() -> Option<Self> {
+        let mut prev: libc::stack_t = mem::zeroed();
+        libc::sigaltstack(ptr::null(), &mut prev);
+        if prev.ss_flags & libc::SS_DISABLE == 0 && prev.ss_size >= ALT_STACK_SIZE {
+            return None;
+        }
+
+        let mut memory = vec![0u8; ALT_STACK_SIZE.max(libc::SIGSTKSZ)].into_boxed_slice();
+        let stack = libc::stack_t {
+            ss_sp: memory.as_mut_ptr() as *mut c_void,
+            ss_flags: 0,
+            ss_size: memory.len(),
+        };
+        if libc::sigaltstack(&stack, ptr::null_mut()) != 0 {
+            return None;
+        }
+        Some(Self {
+            _memory: memory,
+            prev,
+        })
+    }
+}
+
+impl std::fmt::Debug for AltStack {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AltStack { ... }")
+    }
+}
+
+impl Drop for AltStack {
+    fn drop(&mut self) {
+        unsafe {
+            libc::sigaltstack(&self.prev, ptr::null_mut());
+        }
+    }
+}
+
+fn crash_handler(_signum: c_int) -> bool {
+    // Let the signal fall through to `report`, which has access to the
+    // full signal context.
+    false
+}
+
+/// Write a crash summary if a `CrashGuard` is active. Returns `true` if
+/// the summary was written.
+pub(crate) unsafe fn report(signum: c_int, data: PlatformSigData) -> bool {
+    let fd = CRASH_FD.load(Ordering::Relaxed);
+    if fd < 0 || !CRASH_SIGNALS.contains(&signum) {
+        return false;
+    }
+    let (info, ucontext) = data;
+
+    let mut w = SignalWriter::new(fd);
+    w.write_str("Fatal signal ")
+        .write_i64(signum as i64)
+        .write_str(" (")
+        .write_str(signal_name(signum))
+        .write_str(")");
+    if !info.is_null() {
+        w.write_str(", code ")
+            .write_i64((*info).si_code as i64)
+            .write_str(", fault address ")
+            .write_hex((*info).si_addr() as usize as u64);
+    }
+    w.write_str("\n");
+    if !ucontext.is_null() {
+        write_registers(&mut w, &*(ucontext as *const libc::ucontext_t));
+    }
+    true
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+unsafe fn write_registers(w: &mut SignalWriter, ucontext: &libc::ucontext_t) {
+    const NAMES: &[&str] = &[
+        "r8", "r9", "r10", "r11", "r12", "r13", "r14", "r15", "rdi", "rsi", "rbp", "rbx", "rdx",
+        "rax", "rcx", "rsp", "rip", "eflags",
+    ];
+    let gregs = &ucontext.uc_mcontext.gregs;
+    for (name, &value) in NAMES.iter().zip(gregs.iter()) {
+        w.write_str("  ")
+            .write_str(name)
+            .write_str(": ")
+            .write_hex(value as u64)
+            .write_str("\n");
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+unsafe fn write_registers(w: &mut SignalWriter, ucontext: &libc::ucontext_t) {
+    let mcontext = &ucontext.uc_mcontext;
+    for (i, &value) in mcontext.regs.iter().enumerate() {
+        w.write_str("  x")
+            .write_i64(i as i64)
+            .write_str(": ")
+            .write_hex(value as u64)
+            .write_str("\n");
+    }
+    for &(name, value) in &[("sp", mcontext.sp), ("pc", mcontext.pc)] {
+        w.write_str("  ")
+            .write_str(name)
+            .write_str(": ")
+            .write_hex(value as u64)
+            .write_str("\n");
+    }
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+unsafe fn write_registers(_w: &mut SignalWriter, _ucontext: &libc::ucontext_t) {}
+
+/// Restore the default disposition for a signal and re-raise it, so that
+/// the process terminates exactly as it would have done without our
+/// handler (including producing a core dump).
+pub(crate) unsafe fn reraise(signum: c_int) {
+    let mut action: libc::sigaction = mem::zeroed();
+    action.sa_sigaction = libc::SIG_DFL;
+    libc::sigaction(signum, &action, ptr::null_mut());
+    // The signal is blocked whilst we are in the handler, so it will be
+    // delivered as soon as we return. If this was a genuine fault, the
+    // faulting instruction will also be re-executed.
+    libc::raise(signum);
+}
+
+/// Installs a crash handler for `SIGSEGV`, `SIGBUS`, `SIGILL` and `SIGFPE`.
+///
+/// When one of these signals is not handled by any other handler on the
+/// stack, a summary of the crash (the signal, fault address and register
+/// state) is written to the configured file descriptor, and then the signal
+/// is passed on to the previous handler. If there was no previous handler,
+/// the signal is re-raised with its default disposition so that a core dump
+/// is still produced.
+///
+/// An alternate signal stack is installed for the current thread if it
+/// does not already have one, so that stack overflows can also be reported.
+/// Threads spawned via `std::thread` are given an alternate signal stack by
+/// the standard library.
+#[derive(Debug)]
+pub struct CrashGuard {
+    _guard: SignalHandlerGuard<'static>,
+    alt_stack: Option<AltStack>,
+    prev_fd: RawFd,
+    // The alternate signal stack is per-thread
+    _phantom: PhantomData<*const ()>,
+}
+
+impl CrashGuard {
+    /// Install the crash handler, writing crash summaries to `fd`.
+    ///
+    /// The file descriptor must remain open for the lifetime of the guard.
+    ///
+    /// Fails if another handler for one of the crash signals has explicitly
+    /// disabled `SignalOptions::on_stack`.
+    pub fn new(fd: RawFd) -> Result<Self, Error> {
+        // Safety: `crash_handler` is trivially async-signal-safe
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe_with_options(
+                CRASH_SIGNALS,
+                Arc::new(crash_handler),
+                &SignalOptions::new().on_stack(true).name("CrashGuard"),
+            )?
+        };
+        let prev_fd = CRASH_FD.swap(fd, Ordering::Relaxed);
+        let alt_stack = unsafe { AltStack::install() };
+        Ok(Self {
+            _guard: guard,
+            alt_stack,
+            prev_fd,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Install the crash handler, writing crash summaries to `stderr`.
+    pub fn stderr() -> Result<Self, Error> {
+        Self::new(libc::STDERR_FILENO)
+    }
+}
+
+impl Drop for CrashGuard {
+    fn drop(&mut self) {
+        CRASH_FD.store(self.prev_fd, Ordering::Relaxed);
+        self.alt_stack.take();
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+use std::io;
+
+use libc::c_int;
+
+/// Errors which may occur when installing a signal handler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// The requested `SignalOptions` conflict with those requested by
+    /// another handler for the same signal.
+    ConflictingOptions(c_int),
+    /// The signal number is not valid on this platform.
+    InvalidSignal(c_int),
+    /// The signal cannot be handled, eg. `SIGKILL` or `SIGSTOP`.
+    ForbiddenSignal(c_int),
+    /// The operating system failed to install the signal handler. Contains
+    /// the signal number and the OS error code.
+    InstallFailed(c_int, i32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ConflictingOptions(signum) => write!(
+                f,
+                "signal options for signal {} conflict with an existing handler",
+                signum
+            ),
+            Error::InvalidSignal(signum) => write!(f, "invalid signal number {}", signum),
+            Error::ForbiddenSignal(signum) => write!(f, "signal {} cannot be handled", signum),
+            Error::InstallFailed(signum, errno) => write!(
+                f,
+                "failed to install handler for signal {}: {}",
+                signum,
+                io::Error::from_raw_os_error(*errno)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,38 @@
+use libc::c_int;
+
+/// The disposition of a signal prior to our handler being installed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Disposition {
+    /// The default action for the signal.
+    Default,
+    /// The signal was ignored.
+    Ignore,
+    /// A handler was installed by some other code.
+    Handler {
+        /// The address of the handler function.
+        address: usize,
+        /// The `sa_flags` the handler was installed with. Always zero on
+        /// platforms without `sigaction`.
+        flags: c_int,
+    },
+}
+
+/// Information about a signal for which this crate has installed a handler,
+/// as returned by `installed()`.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct SignalInfo {
+    /// The signal number.
+    pub signum: c_int,
+    /// The names of each handler on the stack, from bottom to top. Handlers
+    /// that were not given a name via `SignalOptions::name` are `None`.
+    pub handler_names: Vec<Option<String>>,
+    /// The chain of handlers which will be delegated to if no handler on the
+    /// stack handles the signal, in the order they will be called. There is
+    /// more than one entry only if `reassert` has chained a foreign handler.
+    pub prev: Vec<Disposition>,
+    /// Whether our handler is currently the active handler for the signal.
+    /// If this is `false`, something has replaced it: see `reassert`.
+    pub active: bool,
+}
@@ -16,14 +16,76 @@ use std::sync::Arc;
 use libc::c_int;
 
 mod backend;
+#[cfg(not(windows))]
+mod crash;
+mod error;
+mod introspect;
+#[cfg(not(windows))]
+mod mask;
+mod options;
 mod signal_safe;
 mod stack;
+mod stats;
+mod watch;
+#[cfg(not(windows))]
+mod writer;
 
-pub use stack::Handler;
+#[cfg(not(windows))]
+pub use crash::CrashGuard;
+pub use error::Error;
+pub use introspect::{Disposition, SignalInfo};
+#[cfg(not(windows))]
+pub use mask::{spawn_blocked, spawn_blocked_with, BlockSignalsGuard, SigSet};
+pub use options::SignalOptions;
+pub use stack::{Handler, MAX_SIGNALS};
+pub use stats::SignalStatistics;
+pub use watch::ReassertGuard;
+#[cfg(not(windows))]
+pub use writer::SignalWriter;
+
+/// Returns the signals for which our signal handler has been replaced,
+/// typically because another library installed its own handler without
+/// going through this crate.
+///
+/// When this happens, none of the handlers on the stack for that signal
+/// will be called.
+pub fn verify() -> Vec<c_int> {
+    stack::verify()
+}
+
+/// List every signal for which this crate has installed a signal handler,
+/// along with the handlers on the stack and what was installed previously.
+///
+/// This is intended as a debugging aid.
+pub fn installed() -> Vec<SignalInfo> {
+    stack::installed()
+}
+
+/// Returns delivery statistics for every signal which has been received by
+/// our signal handler at least once.
+pub fn statistics() -> Vec<SignalStatistics> {
+    stats::statistics()
+}
+
+/// Reinstall our signal handler for any signals where it has been replaced.
+///
+/// The foreign handler is chained, such that it will be called if no
+/// handler on the stack handles the signal. If the foreign handler itself
+/// delegates to the handler it replaced, the signal will continue on down
+/// the chain as expected.
+///
+/// Returns the signals whose handlers were reinstalled.
+pub fn reassert() -> Vec<c_int> {
+    unsafe { stack::reassert() }
+}
 
 /// A type may implement this trait to indicate that it can be converted
 /// into an async-signal-safe function. ie. one that is safe to call from
 /// a signal handler.
+///
+/// # Safety
+/// Implementors must guarantee that the converted handler is
+/// async-signal-safe.
 pub unsafe trait SafeHandler: Into<Arc<dyn Handler>> {}
 
 /// This is the primary interface to the crate. When this guard is constructed
@@ -52,19 +114,72 @@ impl<'a> SignalHandlerGuard<'a> {
     ///   with the exception of posting to a `libc` semaphore.
     /// - Calling a function which is not itself marked as async-signal-safe.
     /// - Performing any kind of blocking I/O.
+    ///
+    /// `SignalWriter` may be used to emit diagnostics from within a handler.
+    ///
+    /// # Panics
+    /// Panics if the handler could not be installed. See `try_new_unsafe`.
     pub unsafe fn new_unsafe(signums: &'a [c_int], handler: Arc<dyn Handler>) -> Self {
-        Self {
+        Self::try_new_unsafe(signums, handler).expect("Failed to install signal handler")
+    }
+
+    /// Add a new signal handler, returning an error if it could not be
+    /// installed.
+    ///
+    /// # Safety
+    /// The handler function *must* be `async-signal-safe`. See `new_unsafe`.
+    pub unsafe fn try_new_unsafe(
+        signums: &'a [c_int],
+        handler: Arc<dyn Handler>,
+    ) -> Result<Self, Error> {
+        Self::try_new_unsafe_with_options(signums, handler, &SignalOptions::default())
+    }
+
+    /// Add a new signal handler, specifying how the underlying signal handler
+    /// should be installed.
+    ///
+    /// Fails if the options conflict with those of another handler for the
+    /// same signal, or if the handler could not otherwise be installed.
+    ///
+    /// # Safety
+    /// The handler function *must* be `async-signal-safe`. See `new_unsafe`.
+    pub unsafe fn try_new_unsafe_with_options(
+        signums: &'a [c_int],
+        handler: Arc<dyn Handler>,
+        options: &SignalOptions,
+    ) -> Result<Self, Error> {
+        Ok(Self {
             signums,
-            handler_id: stack::add_handler(signums, handler),
-        }
+            handler_id: stack::add_handler(signums, handler, options)?,
+        })
     }
 
     /// Safely construct a signal guard from a function known statically to be
     /// async-signal-safe.
+    ///
+    /// # Panics
+    /// Panics if the handler could not be installed. See `try_new`.
     pub fn new<H: SafeHandler>(signums: &'a [c_int], handler: H) -> Self {
         unsafe { Self::new_unsafe(signums, handler.into()) }
     }
 
+    /// Safely construct a signal guard from a function known statically to be
+    /// async-signal-safe, returning an error if it could not be installed.
+    pub fn try_new<H: SafeHandler>(signums: &'a [c_int], handler: H) -> Result<Self, Error> {
+        unsafe { Self::try_new_unsafe(signums, handler.into()) }
+    }
+
+    /// Safely construct a signal guard from a function known statically to be
+    /// async-signal-safe, specifying how the underlying signal handler should
+    /// be installed, and returning an error if it could not be installed.
+    pub fn try_new_with_options<H: SafeHandler>(
+        signums: &'a [c_int],
+        handler: H,
+        options: &SignalOptions,
+    ) -> Result<Self, Error> {
+        unsafe { Self::try_new_unsafe_with_options(signums, handler.into(), options) }
+    }
+
     /// Forget this signal guard: the handler will remain attached for the lifetime
     /// of the program.
     pub fn forget(mut self) {
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::thread::{self, JoinHandle};
+
+use libc::c_int;
+
+use super::stack::MAX_SIGNALS;
+use super::Error;
+
+/// A set of signals, used to manipulate the signal mask of a thread.
+#[derive(Copy, Clone)]
+pub struct SigSet(libc::sigset_t);
+
+impl SigSet {
+    /// Construct an empty set of signals.
+    pub fn empty() -> Self {
+        unsafe {
+            let mut set = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            Self(set)
+        }
+    }
+
+    /// Construct a set containing every signal.
+    pub fn full() -> Self {
+        unsafe {
+            let mut set = mem::zeroed();
+            libc::sigfillset(&mut set);
+            Self(set)
+        }
+    }
+
+    /// Returns the signal mask of the current thread.
+    pub fn current() -> Self {
+        unsafe {
+            let mut set = mem::zeroed();
+            libc::pthread_sigmask(libc::SIG_BLOCK, ptr::null(), &mut set);
+            Self(set)
+        }
+    }
+
+    /// Add a signal to the set, returning an error if the signal number is
+    /// not valid.
+    pub fn insert(&mut self, signum: c_int) -> Result<(), Error> {
+        if unsafe { libc::sigaddset(&mut self.0, signum) } == 0 {
+            Ok(())
+        } else {
+            Err(Error::InvalidSignal(signum))
+        }
+    }
+
+    /// Remove a signal from the set.
+    pub fn remove(&mut self, signum: c_int) {
+        unsafe {
+            libc::sigdelset(&mut self.0, signum);
+        }
+    }
+
+    /// Returns `true` if the set contains the signal.
+    pub fn contains(&self, signum: c_int) -> bool {
+        unsafe { libc::sigismember(&self.0, signum) == 1 }
+    }
+
+    /// Add a signal to the set, for use in a builder-style chain.
+    ///
+    /// # Panics
+    /// Panics if the signal number is not valid.
+    pub fn with(mut self, signum: c_int) -> Self {
+        self.insert(signum).expect("Invalid signal number");
+        self
+    }
+
+    /// Remove a signal from the set, for use in a builder-style chain.
+    pub fn without(mut self, signum: c_int) -> Self {
+        self.remove(signum);
+        self
+    }
+
+    /// Iterate over the signals in the set.
+    pub fn iter(&self) -> impl Iterator<Item = c_int> + '_ {
+        (1..MAX_SIGNALS as c_int).filter(move |&signum| self.contains(signum))
+    }
+
+    /// Access the underlying `libc` signal set.
+    pub fn as_raw(&self) -> &libc::sigset_t {
+        &self.0
+    }
+
+    /// Construct from an underlying `libc` signal set.
+    pub fn from_raw(set: libc::sigset_t) -> Self {
+        Self(set)
+    }
+}
+
+impl Default for SigSet {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl PartialEq for SigSet {
+    fn eq(&self, other: &Self) -> bool {
+        (1..MAX_SIGNALS as c_int).all(|signum| self.contains(signum) == other.contains(signum))
+    }
+}
+impl Eq for SigSet {}
+
+impl fmt::Debug for SigSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<c_int> for SigSet {
+    fn from_iter<I: IntoIterator<Item = c_int>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::empty(), Self::with)
+    }
+}
+
+/// Blocks (or unblocks) a set of signals on the current thread for as long
+/// as the guard exists. When dropped, the previous signal mask is restored.
+///
+/// Blocked signals will be delivered to some other thread which does not
+/// block them, or else will remain pending until they are unblocked.
+///
+/// Guards should be dropped in the reverse order to which they were created.
+#[derive(Debug)]
+pub struct BlockSignalsGuard {
+    prev: SigSet,
+    // The signal mask is per-thread
+    _phantom: PhantomData<*const ()>,
+}
+
+impl BlockSignalsGuard {
+    /// Block the signals in the set on the current thread.
+    pub fn new(signals: &SigSet) -> Self {
+        Self::change(libc::SIG_BLOCK, signals)
+    }
+
+    /// Unblock the signals in the set on the current thread.
+    pub fn unblock(signals: &SigSet) -> Self {
+        Self::change(libc::SIG_UNBLOCK, signals)
+    }
+
+    fn change(how: c_int, signals: &SigSet) -> Self {
+        let mut prev = SigSet::empty();
+        unsafe {
+            libc::pthread_sigmask(how, &signals.0, &mut prev.0);
+        }
+        Self {
+            prev,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl Drop for BlockSignalsGuard {
+    fn drop(&mut self) {
+        unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.prev.0, ptr::null_mut());
+        }
+    }
+}
+
+/// Spawn a thread with the given signals blocked from the outset, so that
+/// they will not be delivered to it.
+///
+/// This is useful to ensure that signals are only ever delivered to a
+/// particular thread, or to prevent blocking system calls on worker threads
+/// from being interrupted.
+///
+/// # Panics
+/// Panics if the thread could not be spawned. See `spawn_blocked_with`.
+pub fn spawn_blocked<F, T>(signals: &SigSet, f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_blocked_with(thread::Builder::new(), signals, f).expect("Failed to spawn thread")
+}
+
+/// Spawn a thread from a `std::thread::Builder` with the given signals
+/// blocked from the outset. See `spawn_blocked`.
+pub fn spawn_blocked_with<F, T>(
+    builder: thread::Builder,
+    signals: &SigSet,
+    f: F,
+) -> io::Result<JoinHandle<T>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    // New threads inherit the signal mask of the spawning thread
+    let _guard = BlockSignalsGuard::new(signals);
+    builder.spawn(f)
+}
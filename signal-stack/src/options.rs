@@ -0,0 +1,110 @@
+use libc::c_int;
+
+/// Options controlling how the underlying signal handler is installed.
+///
+/// Each option may be left unspecified, in which case any value requested
+/// by another handler for the same signal will be used, or the default if
+/// no handler expresses a preference. If two handlers for the same signal
+/// explicitly request different values for an option, the second handler
+/// will fail to install.
+///
+/// These options only have an effect on platforms which support `sigaction`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SignalOptions {
+    restart: Option<bool>,
+    on_stack: Option<bool>,
+    no_defer: Option<bool>,
+    mask: Option<Vec<c_int>>,
+    name: Option<String>,
+}
+
+impl SignalOptions {
+    /// Construct a new set of options, with nothing specified.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether to automatically restart interrupted system calls (`SA_RESTART`).
+    /// If disabled, blocking system calls will return `EINTR` when the signal
+    /// is received.
+    ///
+    /// Defaults to `true`.
+    pub fn restart(mut self, value: bool) -> Self {
+        self.restart = Some(value);
+        self
+    }
+
+    /// Whether to run the handler on the alternate signal stack, if one has
+    /// been installed for the thread (`SA_ONSTACK`). The alternate stack is
+    /// typically small, so this should only be enabled for handlers which
+    /// must run even if the thread's stack has overflowed.
+    ///
+    /// Defaults to `false`.
+    pub fn on_stack(mut self, value: bool) -> Self {
+        self.on_stack = Some(value);
+        self
+    }
+
+    /// Whether to allow the signal to be received again whilst its handler
+    /// is still running (`SA_NODEFER`). This only has an effect if the signal
+    /// is not also part of the mask.
+    ///
+    /// Defaults to `false`.
+    pub fn no_defer(mut self, value: bool) -> Self {
+        self.no_defer = Some(value);
+        self
+    }
+
+    /// The set of signals to block whilst the handler is running.
+    ///
+    /// Defaults to all signals.
+    pub fn mask(mut self, signums: &[c_int]) -> Self {
+        let mut mask = signums.to_vec();
+        mask.sort_unstable();
+        mask.dedup();
+        self.mask = Some(mask);
+        self
+    }
+
+    /// A name for the handler, reported by `installed()` to aid debugging.
+    /// Unlike the other options, this applies only to this handler, and so
+    /// cannot conflict with other handlers.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub(crate) fn name_or_default(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub(crate) fn restart_or_default(&self) -> bool {
+        self.restart.unwrap_or(true)
+    }
+    pub(crate) fn on_stack_or_default(&self) -> bool {
+        self.on_stack.unwrap_or(false)
+    }
+    pub(crate) fn no_defer_or_default(&self) -> bool {
+        self.no_defer.unwrap_or(false)
+    }
+    pub(crate) fn mask_or_default(&self) -> Option<&[c_int]> {
+        self.mask.as_deref()
+    }
+
+    /// Combine two sets of options, returning `None` if they conflict.
+    pub(crate) fn merge(&self, other: &Self) -> Option<Self> {
+        fn merge_one<T: Clone + PartialEq>(a: &Option<T>, b: &Option<T>) -> Result<Option<T>, ()> {
+            match (a, b) {
+                (Some(a), Some(b)) if a != b => Err(()),
+                (Some(a), _) => Ok(Some(a.clone())),
+                (None, b) => Ok(b.clone()),
+            }
+        }
+        Some(Self {
+            restart: merge_one(&self.restart, &other.restart).ok()?,
+            on_stack: merge_one(&self.on_stack, &other.on_stack).ok()?,
+            no_defer: merge_one(&self.no_defer, &other.no_defer).ok()?,
+            mask: merge_one(&self.mask, &other.mask).ok()?,
+            name: None,
+        })
+    }
+}
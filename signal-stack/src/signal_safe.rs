@@ -1,85 +1,107 @@
-use std::mem::ManuallyDrop;
-use std::ops::{Deref, DerefMut};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::thread;
 
-use parking_lot::lock_api::RawMutex;
-use parking_lot::{Mutex, MutexGuard};
-
-pub struct RwLock<T> {
-    values: [spin::RwLock<T>; 2],
-    mutex: Mutex<()>,
+/// Allows writers to wait until no reader could still be observing a value
+/// which has been replaced, so that it can be safely freed.
+///
+/// Entering and leaving a read-side critical section is lock-free and never
+/// allocates, so can be done from within a signal handler. Readers are split
+/// into two groups based on the parity of the epoch at the time they started.
+pub struct Epoch {
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
 }
 
-impl<T> RwLock<T> {
-    pub const fn const_new(value1: T, value2: T) -> Self {
+impl Epoch {
+    pub const fn new() -> Self {
         Self {
-            values: [spin::RwLock::new(value1), spin::RwLock::new(value2)],
-            mutex: Mutex::const_new(RawMutex::INIT, ()),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
         }
     }
-    pub fn read(&self) -> ReadGuard<'_, T> {
-        // This can never block, because we only ever lock one value at a time
-        for value in self.values.iter().cycle() {
-            if let Some(inner) = value.try_read() {
-                return ReadGuard { inner };
+
+    pub fn pin(&self) -> EpochGuard<'_> {
+        loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch % 2];
+            readers.fetch_add(1, Ordering::SeqCst);
+            // If the epoch changed in the meantime, a writer may already be
+            // waiting for this group to drain, so don't join it.
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                return EpochGuard { readers };
             }
+            readers.fetch_sub(1, Ordering::SeqCst);
         }
-        unreachable!()
     }
-    pub fn write(&self) -> WriteGuard<'_, T>
-    where
-        T: Clone,
-    {
-        let _mutex_guard = self.mutex.lock();
-        let inner = ManuallyDrop::new(self.values[1].write());
-        let other = &self.values[0];
-        WriteGuard {
-            _mutex_guard,
-            inner,
-            other,
-        }
+
+    /// Wait until every reader which started before this call has finished.
+    ///
+    /// Must not be called concurrently with itself, or from within a
+    /// read-side critical section.
+    pub fn synchronize(&self) {
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        wait_for_readers(&self.readers[epoch.wrapping_add(1) % 2]);
+        self.epoch.store(epoch.wrapping_add(1), Ordering::SeqCst);
+        wait_for_readers(&self.readers[epoch % 2]);
     }
 }
 
-pub struct ReadGuard<'a, T> {
-    inner: spin::RwLockReadGuard<'a, T>,
-}
-impl<'a, T> Deref for ReadGuard<'a, T> {
-    type Target = T;
-    fn deref(&self) -> &T {
-        self.inner.deref()
+fn wait_for_readers(readers: &AtomicUsize) {
+    while readers.load(Ordering::SeqCst) != 0 {
+        thread::yield_now();
     }
 }
 
-pub struct WriteGuard<'a, T: Clone> {
-    _mutex_guard: MutexGuard<'a, ()>,
-    inner: ManuallyDrop<spin::RwLockWriteGuard<'a, T>>,
-    other: &'a spin::RwLock<T>,
+pub struct EpochGuard<'a> {
+    readers: &'a AtomicUsize,
 }
-impl<'a, T: Clone> Deref for WriteGuard<'a, T> {
-    type Target = T;
-    fn deref(&self) -> &T {
-        self.inner.deref()
+
+impl<'a> Drop for EpochGuard<'a> {
+    fn drop(&mut self) {
+        self.readers.fetch_sub(1, Ordering::SeqCst);
     }
 }
-impl<'a, T: Clone> DerefMut for WriteGuard<'a, T> {
-    fn deref_mut(&mut self) -> &mut T {
-        self.inner.deref_mut()
-    }
+
+/// An optional boxed value which can be atomically replaced. Values are
+/// read within an `Epoch` read-side critical section.
+pub struct AtomicBox<T> {
+    ptr: AtomicPtr<T>,
+    _phantom: PhantomData<Box<T>>,
 }
-impl<'a, T: Clone> Drop for WriteGuard<'a, T> {
-    fn drop(&mut self) {
-        // Safety: we only call this once here
-        let guard = unsafe { ManuallyDrop::take(&mut self.inner) };
 
-        // If `clone` panics, the value won't get committed, but it shouldn't
-        // cause any unsafety or deadlocks.
-        let value: T = guard.clone();
+impl<T> AtomicBox<T> {
+    pub const fn new() -> Self {
+        Self {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn load<'g>(&self, _guard: &'g EpochGuard<'_>) -> Option<&'g T> {
+        // Safety: the value cannot be freed until the guard is dropped
+        unsafe { self.ptr.load(Ordering::SeqCst).as_ref() }
+    }
 
-        // Release the original guard
-        drop(guard);
+    /// Safety: the returned value must not be dropped until after the
+    /// corresponding `Epoch` has been synchronized.
+    pub unsafe fn swap(&self, value: Option<Box<T>>) -> Option<Box<T>> {
+        let new_ptr = value.map_or(ptr::null_mut(), Box::into_raw);
+        let old_ptr = self.ptr.swap(new_ptr, Ordering::SeqCst);
+        if old_ptr.is_null() {
+            None
+        } else {
+            Some(Box::from_raw(old_ptr))
+        }
+    }
+}
 
-        // Commit the value by also storing it into the other slot
-        // Go via an upgradeable read lock to prevent readers from starving us
-        *self.other.upgradeable_read().upgrade() = value;
+impl<T> Drop for AtomicBox<T> {
+    fn drop(&mut self) {
+        // We have exclusive access, so there can be no readers
+        unsafe {
+            self.swap(None);
+        }
     }
 }
@@ -1,11 +1,15 @@
-use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use libc::c_int;
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
 
-use super::backend::{PlatformSigData, PlatformSigHandler, SigHandler};
-use super::signal_safe::RwLock;
+use super::backend::{current_thread, PlatformSigData, PlatformSigHandler, SigHandler};
+use super::signal_safe::{AtomicBox, Epoch};
+use super::stats::{self, Outcome};
+use super::{Disposition, Error, SignalInfo, SignalOptions};
 
 /// This trait is implemented for functions which match the required signature
 /// for signal handlers.
@@ -18,23 +22,41 @@ use super::signal_safe::RwLock;
 pub trait Handler: Fn(c_int) -> bool + Send + Sync {}
 impl<T: Fn(c_int) -> bool + Send + Sync> Handler for T {}
 
+#[derive(Clone)]
+struct Entry {
+    handler: Arc<dyn Handler>,
+    options: SignalOptions,
+}
+
 #[derive(Clone)]
 struct Slot {
-    stack: Vec<Arc<dyn Handler>>,
-    prev: PlatformSigHandler,
+    stack: Vec<Entry>,
+    // The chain of previous handlers, most recent last. This normally
+    // has exactly one element, but if a foreign handler replaced ours
+    // and was later re-chained by `reassert`, it is pushed here.
+    prevs: Vec<PlatformSigHandler>,
+    // The combined options of every entry on the stack
+    options: SignalOptions,
 }
 
 impl Slot {
     pub fn new(signum: c_int) -> Self {
         Self {
             stack: Vec::new(),
-            prev: PlatformSigHandler::detect(signum),
+            prevs: vec![PlatformSigHandler::detect(signum)],
+            options: SignalOptions::default(),
         }
     }
+    fn merged_options(&self) -> SignalOptions {
+        self.stack
+            .iter()
+            .try_fold(SignalOptions::default(), |acc, entry| {
+                acc.merge(&entry.options)
+            })
+            .expect("Options on the stack should be compatible")
+    }
 }
 
-type Handlers = HashMap<c_int, Slot>;
-
 #[derive(Clone)]
 pub struct HandlerId(Arc<dyn Handler>);
 
@@ -55,78 +77,388 @@ impl PartialEq for HandlerId {
     }
 }
 
-static HANDLERS: RwLock<Option<Handlers>> = RwLock::const_new(None, None);
+/// One more than the highest signal number which handlers can be installed
+/// for. This is larger than the highest signal number on any supported
+/// platform.
+pub const MAX_SIGNALS: usize = 128;
+
+// Signal handlers never block: they read the slot for their signal within an
+// epoch. Modifications are made to a copy of the slot which is then swapped
+// in, and the old slot is freed once no signal handler can be observing it.
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_SLOT: AtomicBox<Slot> = AtomicBox::new();
+static SLOTS: [AtomicBox<Slot>; MAX_SIGNALS] = [EMPTY_SLOT; MAX_SIGNALS];
+static EPOCH: Epoch = Epoch::new();
+
+// Foreign handlers will typically call back into our handler, since it was
+// the one they replaced. Whilst a thread delegates to a foreign handler, it
+// records how deep into the `prevs` chain it is for that signal, so that it
+// continues down the chain instead of looping. This must be tracked per
+// thread, since the same signal may be delivered to several threads at once.
+struct Delegation {
+    // The thread which claimed this entry, or zero if it is free. The other
+    // fields are only accessed by that thread.
+    thread: AtomicUsize,
+    signum: AtomicUsize,
+    depth: AtomicUsize,
+}
+
+impl Delegation {
+    fn find(thread: usize, signum: c_int) -> Option<&'static Self> {
+        DELEGATIONS.iter().find(|item| {
+            item.thread.load(Ordering::Acquire) == thread
+                && item.signum.load(Ordering::Relaxed) == signum as usize
+        })
+    }
+    fn claim(thread: usize, signum: c_int) -> Option<&'static Self> {
+        let item = DELEGATIONS.iter().find(|item| {
+            item.thread
+                .compare_exchange(0, thread, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        item.signum.store(signum as usize, Ordering::Relaxed);
+        Some(item)
+    }
+    fn release(&self) {
+        self.signum.store(0, Ordering::Relaxed);
+        self.thread.store(0, Ordering::Release);
+    }
+}
+
+// The number of threads which may be delegating to foreign handlers at once
+const MAX_DELEGATIONS: usize = 64;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const FREE_DELEGATION: Delegation = Delegation {
+    thread: AtomicUsize::new(0),
+    signum: AtomicUsize::new(0),
+    depth: AtomicUsize::new(0),
+};
+static DELEGATIONS: [Delegation; MAX_DELEGATIONS] = [FREE_DELEGATION; MAX_DELEGATIONS];
+
+// Serializes all modifications to the slots and to the installed C handlers
+static WRITE_MUTEX: Mutex<()> = Mutex::const_new(RawMutex::INIT, ());
+
+fn slot_index(signum: c_int) -> Option<usize> {
+    if signum > 0 && (signum as usize) < MAX_SIGNALS {
+        Some(signum as usize)
+    } else {
+        None
+    }
+}
 
 pub(crate) fn our_handler(signum: c_int, data: PlatformSigData) {
-    if let Some(handlers) = &*HANDLERS.read() {
-        if let Some(slot) = handlers.get(&signum) {
-            for item in slot.stack.iter().rev() {
-                if item(signum) {
+    let index = match slot_index(signum) {
+        Some(index) => index,
+        None => return,
+    };
+    let guard = EPOCH.pin();
+    if let Some(slot) = SLOTS[index].load(&guard) {
+        let thread = current_thread();
+        let delegation = Delegation::find(thread, signum);
+        let depth = delegation.map_or(0, |item| item.depth.load(Ordering::Relaxed));
+        if depth == 0 {
+            stats::record_received(index);
+            if slot.stack.iter().rev().any(|item| (item.handler)(signum)) {
+                stats::record_outcome(index, Outcome::Handled);
+                return;
+            }
+        }
+        if let Some(prev) = slot.prevs.iter().rev().nth(depth) {
+            // Record this before delegating, since that may not return
+            if depth == 0 {
+                stats::record_outcome(
+                    index,
+                    if prev.is_default() {
+                        Outcome::Defaulted
+                    } else {
+                        Outcome::Delegated
+                    },
+                );
+            }
+            unsafe {
+                #[cfg(not(windows))]
+                if depth == 0 && super::crash::report(signum, data) && prev.is_default() {
+                    super::crash::reraise(signum);
                     return;
                 }
+                if let Disposition::Handler { .. } = prev.disposition() {
+                    match delegation.or_else(|| Delegation::claim(thread, signum)) {
+                        Some(item) => {
+                            item.depth.store(depth + 1, Ordering::Relaxed);
+                            prev.delegate(signum, data);
+                            if depth == 0 {
+                                item.release();
+                            } else {
+                                item.depth.store(depth, Ordering::Relaxed);
+                            }
+                        }
+                        // Too many threads are delegating at once to track
+                        // this one, so skip straight to the end of the chain,
+                        // which cannot call back into us.
+                        None => slot.prevs[0].delegate(signum, data),
+                    }
+                } else {
+                    prev.delegate(signum, data);
+                }
             }
-            unsafe {
-                slot.prev.delegate(signum, data);
+        }
+    }
+}
+
+// The following functions must only be called with `WRITE_MUTEX` held.
+
+fn load_slot(signum: c_int) -> Option<Slot> {
+    let guard = EPOCH.pin();
+    SLOTS[signum as usize].load(&guard).cloned()
+}
+
+fn publish_slots(slots: Vec<(c_int, Option<Slot>)>) {
+    let old: Vec<_> = slots
+        .into_iter()
+        .map(|(signum, slot)| unsafe { SLOTS[signum as usize].swap(slot.map(Box::new)) })
+        .collect();
+    EPOCH.synchronize();
+    drop(old);
+}
+
+// Install our C handler for each signal, using the given options. Returns the
+// previous handler for each signal which was newly installed. On failure,
+// newly installed handlers are uninstalled again.
+unsafe fn install_c_handlers(
+    signums: &[(c_int, SignalOptions, bool)],
+) -> Result<Vec<(c_int, PlatformSigHandler)>, Error> {
+    let mut prevs = Vec::new();
+    for (signum, options, is_new) in signums {
+        match PlatformSigHandler::ours(options).install(*signum) {
+            Ok(prev) => {
+                if *is_new {
+                    prevs.push((*signum, prev));
+                }
+            }
+            Err(e) => {
+                for (signum, prev) in prevs {
+                    let _ = prev.install(signum);
+                }
+                return Err(Error::InstallFailed(*signum, e.raw_os_error().unwrap_or(0)));
             }
         }
     }
+    Ok(prevs)
 }
 
-pub(crate) unsafe fn add_handler(signums: &[c_int], handler: Arc<dyn Handler>) -> HandlerId {
+pub(crate) unsafe fn add_handler(
+    signums: &[c_int],
+    handler: Arc<dyn Handler>,
+    options: &SignalOptions,
+) -> Result<HandlerId, Error> {
     let handler_id = HandlerId(handler.clone());
 
     if !signums.is_empty() {
-        let mut install_c_handlers = Vec::new();
-        {
-            let mut guard = HANDLERS.write();
-            let handlers = guard.get_or_insert_with(Default::default);
-            for &signum in signums {
-                handlers
-                    .entry(signum)
-                    .or_insert_with(|| {
-                        install_c_handlers.push(signum);
-                        Slot::new(signum)
-                    })
-                    .stack
-                    .push(handler.clone());
-            }
+        for &signum in signums {
+            slot_index(signum).ok_or(Error::InvalidSignal(signum))?;
+            PlatformSigHandler::validate(signum)?;
+        }
+
+        let _write_guard = WRITE_MUTEX.lock();
+
+        // Build up the new slots without publishing anything, so that
+        // nothing is changed on failure.
+        let mut changes: Vec<(c_int, Option<Slot>, Slot)> = Vec::new();
+        for &signum in signums {
+            let index = match changes.iter().position(|(s, _, _)| *s == signum) {
+                Some(index) => index,
+                None => {
+                    let original = load_slot(signum);
+                    let slot = original.clone().unwrap_or_else(|| Slot::new(signum));
+                    changes.push((signum, original, slot));
+                    changes.len() - 1
+                }
+            };
+            let slot = &mut changes[index].2;
+            slot.options = slot
+                .options
+                .merge(options)
+                .ok_or(Error::ConflictingOptions(signum))?;
+            slot.stack.push(Entry {
+                handler: handler.clone(),
+                options: options.clone(),
+            });
         }
 
-        if !install_c_handlers.is_empty() {
-            let prevs: Vec<_> = install_c_handlers
-                .into_iter()
-                .map(|signum| (signum, PlatformSigHandler::ours().install(signum)))
-                .collect();
+        let install: Vec<_> = changes
+            .iter()
+            .filter_map(|(signum, original, slot)| match original {
+                None => Some((*signum, slot.options.clone(), true)),
+                Some(original) if original.options != slot.options => {
+                    Some((*signum, slot.options.clone(), false))
+                }
+                Some(_) => None,
+            })
+            .collect();
+
+        // Publish the new slots before installing our C handler, so that
+        // the slot exists by the time our handler might be called.
+        publish_slots(
+            changes
+                .iter()
+                .map(|(signum, _, slot)| (*signum, Some(slot.clone())))
+                .collect(),
+        );
 
-            let mut guard = HANDLERS.write();
-            let handlers = guard.as_mut().unwrap();
-            for (signum, prev) in prevs {
-                handlers.get_mut(&signum).unwrap().prev = prev;
+        match install_c_handlers(&install) {
+            Ok(prevs) => {
+                if !prevs.is_empty() {
+                    publish_slots(
+                        prevs
+                            .into_iter()
+                            .map(|(signum, prev)| {
+                                let mut slot = load_slot(signum).unwrap();
+                                slot.prevs = vec![prev];
+                                (signum, Some(slot))
+                            })
+                            .collect(),
+                    );
+                }
+            }
+            Err(e) => {
+                // Restore the C handlers for existing slots whose options changed
+                let restore: Vec<_> = changes
+                    .iter()
+                    .filter_map(|(signum, original, _)| {
+                        Some((*signum, original.as_ref()?.options.clone(), false))
+                    })
+                    .collect();
+                let _ = install_c_handlers(&restore);
+                publish_slots(
+                    changes
+                        .into_iter()
+                        .map(|(signum, original, _)| (signum, original))
+                        .collect(),
+                );
+                return Err(e);
             }
         }
     }
 
-    handler_id
+    Ok(handler_id)
 }
 
 pub(crate) unsafe fn remove_handler(signums: &[c_int], handler_id: &HandlerId) {
     if signums.is_empty() {
         return;
     }
+    let _write_guard = WRITE_MUTEX.lock();
     let ptr = Arc::as_ptr(&handler_id.0) as *const ();
-    if let Some(handlers) = HANDLERS.write().as_mut() {
-        for &signum in signums {
-            if let Some(slot) = handlers.get_mut(&signum) {
-                if let Some((index, _)) = slot
-                    .stack
-                    .iter()
-                    .enumerate()
-                    .rev()
-                    .find(|&(_, item)| Arc::as_ptr(item) as *const () == ptr)
-                {
-                    slot.stack.remove(index);
+
+    let mut changes: Vec<(c_int, SignalOptions, Slot)> = Vec::new();
+    for &signum in signums {
+        if slot_index(signum).is_none() {
+            continue;
+        }
+        let index = match changes.iter().position(|(s, _, _)| *s == signum) {
+            Some(index) => index,
+            None => match load_slot(signum) {
+                Some(slot) => {
+                    changes.push((signum, slot.options.clone(), slot));
+                    changes.len() - 1
                 }
+                None => continue,
+            },
+        };
+        let slot = &mut changes[index].2;
+        if let Some((index, _)) = slot
+            .stack
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|&(_, item)| Arc::as_ptr(&item.handler) as *const () == ptr)
+        {
+            slot.stack.remove(index);
+            slot.options = slot.merged_options();
+        }
+    }
+
+    let install: Vec<_> = changes
+        .iter()
+        .filter(|(_, original, slot)| *original != slot.options)
+        .map(|(signum, _, slot)| (*signum, slot.options.clone(), false))
+        .collect();
+    publish_slots(
+        changes
+            .into_iter()
+            .map(|(signum, _, slot)| (signum, Some(slot)))
+            .collect(),
+    );
+    // Reverting to less specific options can't reasonably fail, and there's
+    // nothing useful we could do about it if it did.
+    let _ = install_c_handlers(&install);
+}
+
+fn for_each_slot<F: FnMut(c_int, &Slot)>(mut f: F) {
+    let guard = EPOCH.pin();
+    for (index, slot) in SLOTS.iter().enumerate() {
+        if let Some(slot) = slot.load(&guard) {
+            f(index as c_int, slot);
+        }
+    }
+}
+
+pub(crate) fn verify() -> Vec<c_int> {
+    let _write_guard = WRITE_MUTEX.lock();
+    let mut res = Vec::new();
+    for_each_slot(|signum, _| {
+        if !PlatformSigHandler::detect(signum).is_ours() {
+            res.push(signum);
+        }
+    });
+    res
+}
+
+pub(crate) fn installed() -> Vec<SignalInfo> {
+    let _write_guard = WRITE_MUTEX.lock();
+    let mut res = Vec::new();
+    for_each_slot(|signum, slot| {
+        res.push(SignalInfo {
+            signum,
+            handler_names: slot
+                .stack
+                .iter()
+                .map(|entry| entry.options.name_or_default().map(Into::into))
+                .collect(),
+            prev: slot
+                .prevs
+                .iter()
+                .rev()
+                .map(|prev| prev.disposition())
+                .collect(),
+            active: PlatformSigHandler::detect(signum).is_ours(),
+        })
+    });
+    res
+}
+
+pub(crate) unsafe fn reassert() -> Vec<c_int> {
+    let _write_guard = WRITE_MUTEX.lock();
+    let mut overwritten = Vec::new();
+    for_each_slot(|signum, slot| {
+        if !PlatformSigHandler::detect(signum).is_ours() {
+            overwritten.push((signum, slot.clone()));
+        }
+    });
+
+    let mut res = Vec::new();
+    let mut updated = Vec::new();
+    for (signum, mut slot) in overwritten {
+        if let Ok(prev) = PlatformSigHandler::ours(&slot.options).install(signum) {
+            // The handler may have been restored in the meantime
+            if !prev.is_ours() {
+                slot.prevs.push(prev);
+                updated.push((signum, Some(slot)));
+                res.push(signum);
             }
         }
     }
+    publish_slots(updated);
+    res
 }
@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use libc::c_int;
+
+use super::stack::MAX_SIGNALS;
+
+/// Delivery statistics for a single signal, as returned by `statistics()`.
+///
+/// Each counter only ever increases, so these are suitable for exporting
+/// as eg. Prometheus counters.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SignalStatistics {
+    /// The signal number.
+    pub signum: c_int,
+    /// The number of times the signal was received by our handler.
+    pub received: u64,
+    /// The number of times a handler on the stack handled the signal.
+    pub handled: u64,
+    /// The number of times the signal was passed on to a previously
+    /// installed handler, or was ignored.
+    pub delegated: u64,
+    /// The number of times the default action for the signal occurred.
+    pub defaulted: u64,
+}
+
+pub(crate) enum Outcome {
+    Handled,
+    Delegated,
+    Defaulted,
+}
+
+struct Counters {
+    received: AtomicUsize,
+    handled: AtomicUsize,
+    delegated: AtomicUsize,
+    defaulted: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: Counters = Counters {
+    received: AtomicUsize::new(0),
+    handled: AtomicUsize::new(0),
+    delegated: AtomicUsize::new(0),
+    defaulted: AtomicUsize::new(0),
+};
+static COUNTERS: [Counters; MAX_SIGNALS] = [ZERO; MAX_SIGNALS];
+
+// These are called from within the signal handler, so must be async-signal-safe.
+
+pub(crate) fn record_received(index: usize) {
+    COUNTERS[index].received.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn record_outcome(index: usize, outcome: Outcome) {
+    let counters = &COUNTERS[index];
+    match outcome {
+        Outcome::Handled => &counters.handled,
+        Outcome::Delegated => &counters.delegated,
+        Outcome::Defaulted => &counters.defaulted,
+    }
+    .fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn statistics() -> Vec<SignalStatistics> {
+    COUNTERS
+        .iter()
+        .enumerate()
+        .filter(|(_, counters)| counters.received.load(Ordering::Relaxed) > 0)
+        .map(|(index, counters)| SignalStatistics {
+            signum: index as c_int,
+            received: counters.received.load(Ordering::Relaxed) as u64,
+            handled: counters.handled.load(Ordering::Relaxed) as u64,
+            delegated: counters.delegated.load(Ordering::Relaxed) as u64,
+            defaulted: counters.defaulted.load(Ordering::Relaxed) as u64,
+        })
+        .collect()
+}
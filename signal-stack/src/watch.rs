@@ -0,0 +1,53 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc::c_int;
+
+/// Periodically checks whether another library has replaced our signal
+/// handlers, and if so reasserts them. See `reassert` for details.
+///
+/// The check runs on a background thread, which is stopped when this
+/// guard is dropped.
+#[derive(Debug)]
+pub struct ReassertGuard {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ReassertGuard {
+    /// Check our signal handlers every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self::with_callback(interval, |_| {})
+    }
+
+    /// Check our signal handlers every `interval`, calling `callback` with
+    /// the list of signals whose handlers had to be reasserted.
+    pub fn with_callback<F: FnMut(&[c_int]) + Send + 'static>(
+        interval: Duration,
+        mut callback: F,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(interval) {
+                let signums = super::reassert();
+                if !signums.is_empty() {
+                    callback(&signums);
+                }
+            }
+        });
+        Self {
+            stop: Some(tx),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for ReassertGuard {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
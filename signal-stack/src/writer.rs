@@ -0,0 +1,142 @@
+use std::fmt;
+use std::os::unix::io::RawFd;
+
+use libc::c_int;
+
+const BUFFER_SIZE: usize = 256;
+
+/// An async-signal-safe writer, for emitting diagnostics from within a
+/// signal handler.
+///
+/// Output is accumulated in a fixed-size buffer on the stack and written
+/// to the file descriptor using only `write(2)`. The buffer is flushed when
+/// it fills up, when `flush` is called, and when the writer is dropped.
+/// No memory is ever allocated.
+///
+/// Errors are silently ignored, since there is nothing sensible that a
+/// signal handler could do about them.
+///
+/// `SignalWriter` also implements `std::fmt::Write`, so it can be used with
+/// the `write!` macro. Formatting of the primitive types via `core::fmt`
+/// does not allocate, but formatting arbitrary types may, so prefer the
+/// dedicated methods where possible.
+pub struct SignalWriter {
+    fd: RawFd,
+    len: usize,
+    buffer: [u8; BUFFER_SIZE],
+}
+
+impl SignalWriter {
+    /// Construct a writer for the given file descriptor.
+    pub fn new(fd: RawFd) -> Self {
+        Self {
+            fd,
+            len: 0,
+            buffer: [0; BUFFER_SIZE],
+        }
+    }
+
+    /// Construct a writer for `stderr`.
+    pub fn stderr() -> Self {
+        Self::new(libc::STDERR_FILENO)
+    }
+
+    /// Write any buffered output to the file descriptor.
+    pub fn flush(&mut self) {
+        write_all(self.fd, &self.buffer[0..self.len]);
+        self.len = 0;
+    }
+
+    /// Write raw bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> &mut Self {
+        if bytes.len() > BUFFER_SIZE - self.len {
+            self.flush();
+        }
+        if bytes.len() > BUFFER_SIZE {
+            write_all(self.fd, bytes);
+        } else {
+            self.buffer[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        self
+    }
+
+    /// Write a string.
+    pub fn write_str(&mut self, s: &str) -> &mut Self {
+        self.write_bytes(s.as_bytes())
+    }
+
+    /// Write an unsigned integer in decimal.
+    pub fn write_u64(&mut self, mut value: u64) -> &mut Self {
+        let mut buf = [0u8; 20];
+        let mut pos = buf.len();
+        loop {
+            pos -= 1;
+            buf[pos] = b'0' + (value % 10) as u8;
+            value /= 10;
+            if value == 0 {
+                break;
+            }
+        }
+        self.write_bytes(&buf[pos..])
+    }
+
+    /// Write a signed integer in decimal.
+    pub fn write_i64(&mut self, value: i64) -> &mut Self {
+        if value < 0 {
+            self.write_str("-");
+        }
+        self.write_u64(value.unsigned_abs())
+    }
+
+    /// Write an integer as zero-padded hexadecimal, prefixed with `0x`.
+    pub fn write_hex(&mut self, value: u64) -> &mut Self {
+        const DIGITS: &[u8; 16] = b"0123456789abcdef";
+        let mut buf = [0u8; 18];
+        buf[0] = b'0';
+        buf[1] = b'x';
+        for i in 0..16 {
+            buf[17 - i] = DIGITS[((value >> (i * 4)) & 0xf) as usize];
+        }
+        self.write_bytes(&buf)
+    }
+}
+
+impl fmt::Write for SignalWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        SignalWriter::write_str(self, s);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SignalWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SignalWriter")
+            .field("fd", &self.fd)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for SignalWriter {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+fn write_all(fd: RawFd, mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        let res = unsafe { libc::write(fd, bytes.as_ptr() as *const _, bytes.len()) };
+        if res > 0 {
+            bytes = &bytes[res as usize..];
+        } else if res < 0 && errno() == libc::EINTR {
+            continue;
+        } else {
+            return;
+        }
+    }
+}
+
+fn errno() -> c_int {
+    std::io::Error::last_os_error().raw_os_error().unwrap_or(0)
+}
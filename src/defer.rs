@@ -0,0 +1,144 @@
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::{Condvar, Mutex};
+
+struct Shields {
+    unbounded: usize,
+    bounded: Vec<Duration>,
+    // The thread which created each shield.
+    holders: Vec<ThreadId>,
+}
+
+impl Shields {
+    fn is_empty(&self) -> bool {
+        self.unbounded == 0 && self.bounded.is_empty()
+    }
+    // The maximum time a shutdown request may currently be deferred for.
+    fn limit(&self) -> Option<Duration> {
+        if self.unbounded > 0 {
+            None
+        } else {
+            self.bounded.iter().copied().min()
+        }
+    }
+}
+
+static SHIELDS: Mutex<Shields> = Mutex::const_new(
+    RawMutex::INIT,
+    Shields {
+        unbounded: 0,
+        bounded: Vec::new(),
+        holders: Vec::new(),
+    },
+);
+static RELEASED: Condvar = Condvar::new();
+
+/// Defer the handling of shutdown requests for as long as the returned
+/// shield exists.
+///
+/// Shutdown requests received whilst any shield is held are queued, and
+/// delivered to the shutdown handler once the last shield is dropped. If
+/// no shutdown handler is installed at that point, the process exits as
+/// usual.
+pub fn defer_shutdown() -> ShutdownShield {
+    let holder = thread::current().id();
+    let mut shields = SHIELDS.lock();
+    shields.unbounded += 1;
+    shields.holders.push(holder);
+    ShutdownShield {
+        max_deferral: None,
+        holder,
+    }
+}
+
+/// Defer the handling of shutdown requests for as long as the returned
+/// shield exists, but for no longer than `max_deferral` after each request
+/// is received. See `defer_shutdown`.
+pub fn defer_shutdown_for(max_deferral: Duration) -> ShutdownShield {
+    let holder = thread::current().id();
+    let mut shields = SHIELDS.lock();
+    shields.bounded.push(max_deferral);
+    shields.holders.push(holder);
+    ShutdownShield {
+        max_deferral: Some(max_deferral),
+        holder,
+    }
+}
+
+/// Defers the handling of shutdown requests until it is dropped. See
+/// `defer_shutdown`.
+#[derive(Debug)]
+#[must_use = "shutdown requests are only deferred whilst the shield exists"]
+pub struct ShutdownShield {
+    max_deferral: Option<Duration>,
+    holder: ThreadId,
+}
+
+impl Drop for ShutdownShield {
+    fn drop(&mut self) {
+        let mut shields = SHIELDS.lock();
+        match self.max_deferral {
+            None => shields.unbounded -= 1,
+            Some(max_deferral) => {
+                if let Some(index) = shields.bounded.iter().position(|&d| d == max_deferral) {
+                    shields.bounded.swap_remove(index);
+                }
+            }
+        }
+        if let Some(index) = shields.holders.iter().position(|&h| h == self.holder) {
+            shields.holders.swap_remove(index);
+        }
+        // The remaining shields may permit a shorter deferral
+        RELEASED.notify_all();
+        let released = shields.is_empty();
+        drop(shields);
+        if released {
+            super::release_deferred();
+        }
+    }
+}
+
+// Block until no shields are held, or until the maximum deferral of the
+// remaining shields has elapsed since `received`. Returns `false` without
+// waiting any further if `cancelled` returns `true` after a call to `wake`.
+pub(crate) fn wait<F: Fn() -> bool>(received: Instant, cancelled: F) -> bool {
+    let mut shields = SHIELDS.lock();
+    while !shields.is_empty() {
+        if cancelled() {
+            return false;
+        }
+        match shields.limit() {
+            None => RELEASED.wait(&mut shields),
+            Some(limit) => {
+                if RELEASED
+                    .wait_until(&mut shields, received + limit)
+                    .timed_out()
+                {
+                    break;
+                }
+            }
+        }
+    }
+    true
+}
+
+// Returns `true` if any shields are held.
+pub(crate) fn is_deferred() -> bool {
+    !SHIELDS.lock().is_empty()
+}
+
+// Returns `true` if the current thread created a shield which still exists,
+// in which case it must not `wait` for the shields to be released.
+pub(crate) fn is_held() -> bool {
+    let current = thread::current().id();
+    SHIELDS.lock().holders.contains(&current)
+}
+
+// Wake any threads blocked in `wait`, so that they re-check whether they
+// have been cancelled.
+pub(crate) fn wake() {
+    let _shields = SHIELDS.lock();
+    RELEASED.notify_all();
+}
@@ -0,0 +1,44 @@
+use std::fmt;
+use std::io;
+
+/// Errors which may occur when installing a shutdown handler.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The underlying signal handler could not be installed.
+    #[cfg(not(windows))]
+    Signal(signal_stack::Error),
+    /// A call to the operating system failed.
+    Os(io::Error),
+    /// The background thread used to run shutdown handlers could not be
+    /// spawned.
+    ThreadSpawn(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(not(windows))]
+            Error::Signal(e) => write!(f, "failed to install signal handler: {}", e),
+            Error::Os(e) => write!(f, "failed to install shutdown handler: {}", e),
+            Error::ThreadSpawn(e) => write!(f, "failed to spawn background thread: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(not(windows))]
+            Error::Signal(e) => Some(e),
+            Error::Os(e) | Error::ThreadSpawn(e) => Some(e),
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl From<signal_stack::Error> for Error {
+    fn from(e: signal_stack::Error) -> Self {
+        Error::Signal(e)
+    }
+}
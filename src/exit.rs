@@ -0,0 +1,139 @@
+use std::os::raw::c_int;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+#[cfg(not(windows))]
+use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+
+#[cfg(not(windows))]
+use super::unix::{is_default, is_intercepted, RawSemaphore};
+
+type Hook = Box<dyn FnOnce() + Send>;
+
+static HOOKS: Mutex<Vec<Hook>> = Mutex::const_new(RawMutex::INIT, Vec::new());
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static RAN: AtomicBool = AtomicBool::new(false);
+#[cfg(not(windows))]
+static EXIT_SEM: RawSemaphore = RawSemaphore::new();
+#[cfg(not(windows))]
+static EXIT_SIGNAL: AtomicI32 = AtomicI32::new(0);
+
+extern "C" {
+    pub(crate) fn atexit(callback: extern "C" fn()) -> c_int;
+}
+
+// Signals whose default action terminates the process, and which are
+// commonly sent to request that a process exits.
+#[cfg(not(windows))]
+const SIGNALS: &[&[c_int]] = &[
+    &[libc::SIGINT],
+    &[libc::SIGTERM],
+    &[libc::SIGHUP],
+    &[libc::SIGQUIT],
+    &[libc::SIGUSR1],
+    &[libc::SIGUSR2],
+    &[libc::SIGALRM],
+];
+
+/// Register a function to be called when the process exits, whether by
+/// returning from `main`, calling `std::process::exit`, or because a
+/// shutdown request was received with no `ShutdownGuard` installed.
+///
+/// Hooks run exactly once, in the reverse order to which they were
+/// registered. They should not create or drop any guards from this crate.
+///
+/// As a best effort, hooks are also run when a signal is received whose
+/// default action is to terminate the process, and which is not otherwise
+/// handled. In that case, they run on a background thread whilst the rest
+/// of the program continues, after which the default action of the signal
+/// takes place as usual.
+pub fn on_exit<F: FnOnce() + Send + 'static>(hook: F) {
+    HOOKS.lock().push(Box::new(hook));
+    if !INSTALLED.swap(true, Ordering::SeqCst) {
+        unsafe {
+            atexit(run_at_exit);
+        }
+        #[cfg(not(windows))]
+        install_signal_handlers();
+    }
+}
+
+fn run_hooks(hooks: Vec<Hook>) {
+    for hook in hooks.into_iter().rev() {
+        // Unwinding out of an `extern "C"` function would abort
+        let _ = catch_unwind(AssertUnwindSafe(hook));
+    }
+}
+
+extern "C" fn run_at_exit() {
+    let hooks = {
+        let mut hooks = HOOKS.lock();
+        if RAN.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        std::mem::take(&mut *hooks)
+    };
+    run_hooks(hooks);
+}
+
+#[cfg(not(windows))]
+fn install_signal_handlers() {
+    use signal_stack::SignalHandlerGuard;
+    use std::sync::Arc;
+    use std::thread;
+
+    // Hooks run on a dedicated thread, since they need not be
+    // async-signal-safe.
+    if unsafe { EXIT_SEM.init() }.is_err() {
+        return;
+    }
+    if thread::Builder::new()
+        .name("grace-exit".into())
+        .spawn(exit_thread)
+        .is_err()
+    {
+        return;
+    }
+
+    for &signums in SIGNALS {
+        // Only take over signals which would otherwise terminate the process,
+        // taking into account any handlers of our own which are installed.
+        if !is_default(signums[0]) {
+            continue;
+        }
+        let handler = Arc::new(|signum| {
+            // Let the signal through to a handler of ours which consumes it,
+            // whether it was installed before or after this one.
+            if is_intercepted(signum) {
+                return false;
+            }
+            // Only the first such signal is acted upon
+            let _ = EXIT_SIGNAL.compare_exchange(0, signum, Ordering::SeqCst, Ordering::SeqCst);
+            EXIT_SEM.post();
+            true
+        });
+        if let Ok(guard) = unsafe { SignalHandlerGuard::try_new_unsafe(signums, handler) } {
+            guard.forget();
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn exit_thread() {
+    use signal_stack::{BlockSignalsGuard, SigSet};
+
+    EXIT_SEM.wait();
+    let signum = EXIT_SIGNAL.load(Ordering::SeqCst);
+    run_at_exit();
+
+    // Terminate via the default action of the signal, so that the exit
+    // status is exactly as it would have been without the hooks.
+    let _unblock = BlockSignalsGuard::unblock(&SigSet::empty().with(signum));
+    unsafe {
+        libc::signal(signum, libc::SIG_DFL);
+        libc::raise(signum);
+        libc::_exit(128 + signum);
+    }
+}
@@ -0,0 +1,212 @@
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+use signal_stack::SignalHandlerGuard;
+
+use super::unix::Intercept;
+use super::worker::{Wakeup, Worker};
+use super::{Error, ShutdownGuard, ShutdownType};
+
+/// Options controlling which signals are forwarded by a `ForwardGuard`,
+/// and how.
+#[derive(Clone, Debug)]
+pub struct ForwardOptions<'a> {
+    types: &'a [ShutdownType],
+    signals: &'a [c_int],
+    translate: Vec<(c_int, c_int)>,
+    process_group: bool,
+    kill_after: Option<Duration>,
+}
+
+impl<'a> Default for ForwardOptions<'a> {
+    fn default() -> Self {
+        Self {
+            types: &[ShutdownType::Interrupt, ShutdownType::Terminate],
+            signals: &[],
+            translate: Vec::new(),
+            process_group: false,
+            kill_after: None,
+        }
+    }
+}
+
+impl<'a> ForwardOptions<'a> {
+    /// Construct the default options, which forward interrupt and terminate
+    /// requests as-is to a single process.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The shutdown requests to forward. Each is forwarded as the
+    /// corresponding signal, eg. `SIGINT` for `ShutdownType::Interrupt`.
+    pub fn types(mut self, types: &'a [ShutdownType]) -> Self {
+        self.types = types;
+        self
+    }
+
+    /// Additional raw signals to forward. These are forwarded directly from
+    /// the signal handler, rather than via the shutdown handler thread.
+    pub fn signals(mut self, signals: &'a [c_int]) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    /// Send `to` instead of `from` when forwarding the signal `from`.
+    pub fn translate(mut self, from: c_int, to: c_int) -> Self {
+        self.translate.retain(|&(f, _)| f != from);
+        self.translate.push((from, to));
+        self
+    }
+
+    /// Forward signals to the entire process group whose ID is the target
+    /// process ID, rather than just to the target process. The child should
+    /// have been spawned into its own process group for this to be useful.
+    pub fn process_group(mut self, value: bool) -> Self {
+        self.process_group = value;
+        self
+    }
+
+    /// Send `SIGKILL` to the target if it still exists this long after the
+    /// first signal is forwarded.
+    pub fn kill_after(mut self, timeout: Duration) -> Self {
+        self.kill_after = Some(timeout);
+        self
+    }
+}
+
+struct Shared {
+    target: libc::pid_t,
+    translate: Vec<(c_int, c_int)>,
+    wakeup: Arc<Wakeup>,
+}
+
+impl Shared {
+    // This is async-signal-safe.
+    fn forward(&self, signum: c_int) {
+        let signum = self
+            .translate
+            .iter()
+            .find(|&&(from, _)| from == signum)
+            .map_or(signum, |&(_, to)| to);
+        unsafe {
+            libc::kill(self.target, signum);
+        }
+        self.wakeup.wake();
+    }
+
+    fn escalate(&self, timeout: Duration) {
+        if !self.wakeup.wait() {
+            return;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.wakeup.is_stopping() {
+                return;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            thread::park_timeout(deadline - now);
+        }
+        unsafe {
+            libc::kill(self.target, libc::SIGKILL);
+        }
+    }
+}
+
+/// Forwards shutdown requests and other signals received by this process
+/// to another process, for as long as the guard exists.
+///
+/// This takes priority over any existing `ShutdownGuard` for the forwarded
+/// shutdown types, in the same way as if a new `ShutdownGuard` was created.
+pub struct ForwardGuard<'a> {
+    shared: Arc<Shared>,
+    shutdown: Option<ShutdownGuard<'a>>,
+    signals: Option<SignalHandlerGuard<'a>>,
+    intercepts: Vec<Intercept>,
+    worker: Option<Worker>,
+}
+
+/// Forward interrupt and terminate requests to a child process.
+pub fn forward_to(child: &Child) -> Result<ForwardGuard<'static>, Error> {
+    ForwardGuard::new(child.id(), &ForwardOptions::new())
+}
+
+impl<'a> ForwardGuard<'a> {
+    /// Forward signals to the process `pid` according to `options`.
+    ///
+    /// If the process has already exited and been reaped, its ID may have
+    /// been reused, so the guard should be dropped once the process has
+    /// been waited on.
+    pub fn new(pid: u32, options: &ForwardOptions<'a>) -> Result<Self, Error> {
+        let pid = pid as libc::pid_t;
+        let shared = Arc::new(Shared {
+            target: if options.process_group { -pid } else { pid },
+            translate: options.translate.clone(),
+            wakeup: Wakeup::new()?,
+        });
+
+        let mut res = Self {
+            shared,
+            shutdown: None,
+            signals: None,
+            intercepts: Vec::new(),
+            worker: None,
+        };
+
+        if let Some(timeout) = options.kill_after {
+            let thread_shared = res.shared.clone();
+            res.worker = Some(Worker::spawn(
+                "grace-forward",
+                &res.shared.wakeup,
+                move || thread_shared.escalate(timeout),
+            )?);
+        }
+
+        if !options.signals.is_empty() {
+            res.intercepts = options.signals.iter().map(|&s| Intercept::new(s)).collect();
+            let handler_shared = res.shared.clone();
+            res.signals = Some(unsafe {
+                SignalHandlerGuard::try_new_unsafe(
+                    options.signals,
+                    Arc::new(move |signum| {
+                        handler_shared.forward(signum);
+                        true
+                    }),
+                )?
+            });
+        }
+
+        let handler_shared = res.shared.clone();
+        res.shutdown = Some(ShutdownGuard::try_new(options.types, move |type_| {
+            handler_shared.forward(match type_ {
+                ShutdownType::Interrupt => libc::SIGINT,
+                ShutdownType::Terminate | ShutdownType::ParentExited => libc::SIGTERM,
+            })
+        })?);
+
+        Ok(res)
+    }
+}
+
+impl<'a> std::fmt::Debug for ForwardGuard<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ForwardGuard")
+            .field("target", &self.shared.target)
+            .finish()
+    }
+}
+
+impl<'a> Drop for ForwardGuard<'a> {
+    fn drop(&mut self) {
+        // Stop forwarding before stopping the escalation thread
+        self.shutdown.take();
+        self.signals.take();
+        self.intercepts.clear();
+        self.worker.take();
+    }
+}
@@ -11,12 +11,13 @@
 //! crate.
 #![deny(missing_docs)]
 
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::mem::ManuallyDrop;
 use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 
 use parking_lot::lock_api::RawMutex;
 use parking_lot::Mutex;
@@ -26,9 +27,63 @@ use futures::channel::mpsc as async_mpsc;
 #[cfg(feature = "futures")]
 use futures::SinkExt;
 
+mod defer;
+mod error;
+mod exit;
+#[cfg(not(windows))]
+mod forward;
+#[cfg(not(windows))]
+mod listener;
+mod panic_hook;
+#[cfg(not(windows))]
+mod parent;
+#[cfg(not(windows))]
+mod reaper;
+#[cfg(not(windows))]
+mod reload;
+#[cfg(not(windows))]
+mod source;
+mod stats;
+#[cfg(not(windows))]
+mod suspend;
+#[cfg(all(feature = "systemd", not(windows)))]
+pub mod systemd;
+#[cfg(not(windows))]
+mod terminal;
+#[cfg(not(windows))]
+pub mod upgrade;
+#[cfg(not(windows))]
+mod worker;
+
+pub use defer::{defer_shutdown, defer_shutdown_for, ShutdownShield};
+pub use error::Error;
+pub use exit::on_exit;
+#[cfg(not(windows))]
+pub use forward::{forward_to, ForwardGuard, ForwardOptions};
+#[cfg(not(windows))]
+pub use listener::{DrainToken, GracefulListener, Listen};
+pub use panic_hook::{panic_message, shutdown_on_panic, shutdown_on_panic_in};
+#[cfg(not(windows))]
+pub use reaper::ChildReaper;
+#[cfg(not(windows))]
+pub use reload::{reload_status, ReloadError, ReloadGuard, ReloadHandler, ReloadStatus};
+#[cfg(not(windows))]
+pub use source::ShutdownSource;
+pub use stats::{statistics, ShutdownStatistics};
+#[cfg(not(windows))]
+pub use suspend::SuspendGuard;
+#[cfg(not(windows))]
+pub use terminal::TerminalGuard;
+
 static STATE: Mutex<Option<State>> = Mutex::const_new(RawMutex::INIT, None);
 
-/// This crate currently distinguishes two kinds of shutdown request.
+thread_local! {
+    // Set whilst this thread is calling a shutdown handler, and so holds the
+    // state lock.
+    static DISPATCHING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// This crate currently distinguishes several kinds of shutdown request.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[non_exhaustive]
 pub enum ShutdownType {
@@ -38,6 +93,11 @@ pub enum ShutdownType {
     /// Program was requested to terminate normally. This corresponds
     /// to `SIGTERM` on unix-based platforms.
     Terminate,
+    /// The parent process exited. On linux this is detected via
+    /// `PR_SET_PDEATHSIG`, which fires when the thread which spawned this
+    /// process exits. On other unix-based platforms the parent process is
+    /// polled for. This is not supported on windows.
+    ParentExited,
 }
 
 /// This trait is implemented for functions which match the required signature
@@ -57,16 +117,91 @@ pub enum ShutdownType {
 pub trait Handler: FnMut(ShutdownType) + Send + 'static {}
 impl<T: FnMut(ShutdownType) + Send + 'static> Handler for T {}
 
+/// This trait is implemented for functions which match the required signature
+/// for shutdown handlers with an explicit `Delivery` mode.
+///
+/// In addition to the shutdown request type, the number of requests being
+/// delivered by this call is passed in as a parameter. See `Handler`.
+pub trait CountedHandler: FnMut(ShutdownType, usize) + Send + 'static {}
+impl<T: FnMut(ShutdownType, usize) + Send + 'static> CountedHandler for T {}
+
+/// Controls how repeated shutdown requests are delivered to a shutdown
+/// handler, such as when Ctrl + C is held down.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Delivery {
+    /// Call the handler once for every request. This is the default.
+    #[default]
+    EveryTime,
+    /// Call the handler once for all of the requests which are pending when
+    /// the handler is woken up, passing the number of requests.
+    Coalesce,
+    /// Call the handler for the first request of each kind only.
+    FirstOnly,
+    /// Call the handler for a request only if no other request of the same
+    /// kind was received within the given duration beforehand. A burst of
+    /// requests therefore results in a single call, at the start of the burst.
+    Debounce(Duration),
+}
+
+struct Registration<H: ?Sized> {
+    delivery: Delivery,
+    // When a request of each kind was last received by this handler.
+    last_received: UnsafeCell<HashMap<ShutdownType, Instant>>,
+    handler: UnsafeCell<H>,
+}
+
+// The handler and the times at which requests were received are only
+// accessed whilst the state mutex is locked, so a registration may be shared
+// between threads as long as the handler itself may be sent between them.
+unsafe impl<H: ?Sized + Send> Send for Registration<H> {}
+unsafe impl<H: ?Sized + Send> Sync for Registration<H> {}
+
+type SharedRegistration = Arc<Registration<dyn CountedHandler>>;
+
+impl Registration<dyn CountedHandler> {
+    // Returns how many of the requests should be passed to the handler.
+    // Safety: must only be called whilst the state mutex is locked.
+    unsafe fn accept(&self, type_: ShutdownType, count: usize, received: Instant) -> usize {
+        let prev = (*self.last_received.get()).insert(type_, received);
+        match self.delivery {
+            Delivery::EveryTime | Delivery::Coalesce => count,
+            Delivery::FirstOnly => match prev {
+                Some(_) => 0,
+                None => 1,
+            },
+            Delivery::Debounce(interval) => match prev {
+                Some(prev) if received.saturating_duration_since(prev) < interval => 0,
+                _ => 1,
+            },
+        }
+    }
+    // Safety: must only be called whilst the state mutex is locked.
+    unsafe fn call(&self, type_: ShutdownType, count: usize) {
+        let handler = &mut *self.handler.get();
+        match self.delivery {
+            Delivery::EveryTime => {
+                for _ in 0..count {
+                    let _ = catch_unwind(AssertUnwindSafe(|| handler(type_, 1)));
+                }
+            }
+            _ => {
+                let _ = catch_unwind(AssertUnwindSafe(|| handler(type_, count)));
+            }
+        }
+    }
+}
+
 struct Slot {
     guard: ManuallyDrop<InternalGuard>,
-    handlers: Vec<Arc<UnsafeCell<dyn Handler>>>,
+    handlers: Vec<SharedRegistration>,
 }
 
 impl Slot {
-    fn new(type_: ShutdownType) -> Self {
-        let guard = unsafe { ManuallyDrop::new(enter(type_)) };
+    fn new(type_: ShutdownType) -> Result<Self, Error> {
+        let guard = unsafe { ManuallyDrop::new(enter(type_)?) };
         let handlers = Vec::new();
-        Self { guard, handlers }
+        Ok(Self { guard, handlers })
     }
 }
 
@@ -80,18 +215,14 @@ struct State {
     slots: HashMap<ShutdownType, Slot>,
 }
 
-// Our handlers need not be `Sync`, because we never call them concurrently. That said,
-// we do need to keep multiple references to them, so we can't use a `Box`.
-unsafe impl Send for State {}
-
 impl State {
-    fn new() -> Self {
+    fn new() -> Result<Self, Error> {
         unsafe {
-            enter_outer();
+            enter_outer()?;
         }
-        Self {
+        Ok(Self {
             slots: HashMap::new(),
-        }
+        })
     }
 }
 impl Drop for State {
@@ -102,23 +233,47 @@ impl Drop for State {
     }
 }
 
-fn handle(type_: ShutdownType) {
+// Deliver `count` requests of the given type, which were received together
+// at `received`. Any shields should already have been waited for.
+fn handle(type_: ShutdownType, count: usize, received: Instant) {
     let guard = STATE.lock();
     if let Some(state) = guard.as_ref() {
         if let Some(slot) = state.slots.get(&type_) {
             if let Some(handler) = slot.handlers.last() {
                 // Safety: We only call the function when we have locked the state mutex,
                 // so guaranteed no other accessors.
-                let _ = catch_unwind(AssertUnwindSafe(|| unsafe { (*handler.get())(type_) }));
+                let accepted = unsafe { handler.accept(type_, count, received) };
+                stats::record(type_, stats::Outcome::Suppressed, count - accepted);
+                if accepted > 0 {
+                    #[cfg(all(feature = "systemd", not(windows)))]
+                    let _extend = systemd::on_shutdown();
+                    stats::record(type_, stats::Outcome::Handled, accepted);
+                    DISPATCHING.with(|dispatching| dispatching.set(true));
+                    unsafe { handler.call(type_, accepted) };
+                    DISPATCHING.with(|dispatching| dispatching.set(false));
+                }
                 return;
             }
         }
     }
 
-    // Handler must have been removed, terminate the process
+    // Handler must have been removed, terminate the process. Exit hooks
+    // run during `exit`, so must not be blocked by the lock.
+    drop(guard);
+    stats::record(type_, stats::Outcome::Unhandled, count);
     std::process::exit(3);
 }
 
+/// Request a shutdown from within the program, as though the corresponding
+/// signal or console event had been received.
+///
+/// The request is delivered to the most recent `ShutdownGuard` for that
+/// kind of shutdown, or else the process exits. This can be used to
+/// implement custom sources of shutdown requests.
+pub fn request_shutdown(type_: ShutdownType) {
+    request(type_);
+}
+
 /// This is the primary interface to the crate.
 ///
 /// Construct an instance of this type to begin intercepting shutdown requests.
@@ -127,13 +282,54 @@ fn handle(type_: ShutdownType) {
 /// There are several constructors to simplify common usage patterns.
 pub struct ShutdownGuard<'a> {
     types: &'a [ShutdownType],
-    handler: Arc<UnsafeCell<dyn Handler>>,
+    handler: SharedRegistration,
 }
 
 impl<'a> ShutdownGuard<'a> {
     /// Call a user-defined function whenever a shutdown is requested.
+    ///
+    /// # Panics
+    /// Panics if the shutdown handler could not be installed. See `try_new`.
     pub fn new<H: Handler>(types: &'a [ShutdownType], handler: H) -> Self {
-        unsafe { Self::new_inner(types, Arc::new(UnsafeCell::new(handler))) }
+        Self::try_new(types, handler).expect("Failed to install shutdown handler")
+    }
+    /// Call a user-defined function whenever a shutdown is requested,
+    /// returning an error if the shutdown handler could not be installed.
+    pub fn try_new<H: Handler>(types: &'a [ShutdownType], mut handler: H) -> Result<Self, Error> {
+        Self::try_with_delivery(types, Delivery::EveryTime, move |type_, _| handler(type_))
+    }
+    /// Call a user-defined function when a shutdown is requested, according
+    /// to the given delivery mode.
+    ///
+    /// # Panics
+    /// Panics if the shutdown handler could not be installed. See
+    /// `try_with_delivery`.
+    pub fn with_delivery<H: CountedHandler>(
+        types: &'a [ShutdownType],
+        delivery: Delivery,
+        handler: H,
+    ) -> Self {
+        Self::try_with_delivery(types, delivery, handler)
+            .expect("Failed to install shutdown handler")
+    }
+    /// Call a user-defined function when a shutdown is requested, according
+    /// to the given delivery mode, returning an error if the shutdown handler
+    /// could not be installed.
+    pub fn try_with_delivery<H: CountedHandler>(
+        types: &'a [ShutdownType],
+        delivery: Delivery,
+        handler: H,
+    ) -> Result<Self, Error> {
+        unsafe {
+            Self::new_inner(
+                types,
+                Arc::new(Registration {
+                    delivery,
+                    last_received: UnsafeCell::new(HashMap::new()),
+                    handler: UnsafeCell::new(handler),
+                }),
+            )
+        }
     }
     /// Send on an mpsc channel whenever a shutdown is requested.
     pub fn new_channel(types: &'a [ShutdownType]) -> (Self, mpsc::Receiver<ShutdownType>) {
@@ -159,21 +355,43 @@ impl<'a> ShutdownGuard<'a> {
         )
     }
     // Safety: the `Arc` must not be shared elsewhere
-    unsafe fn new_inner(types: &'a [ShutdownType], handler: Arc<UnsafeCell<dyn Handler>>) -> Self {
+    unsafe fn new_inner(
+        types: &'a [ShutdownType],
+        handler: SharedRegistration,
+    ) -> Result<Self, Error> {
         if !types.is_empty() {
             let mut guard = STATE.lock();
-            let state = guard.get_or_insert_with(State::new);
+            if guard.is_none() {
+                *guard = Some(State::new()?);
+            }
+            let state = guard.as_mut().unwrap();
+
+            // Install everything up-front so that we can back out on failure
+            let res = types.iter().try_for_each(|&type_| {
+                if let Entry::Vacant(vac) = state.slots.entry(type_) {
+                    vac.insert(Slot::new(type_)?);
+                }
+                Ok(())
+            });
+            if let Err(e) = res {
+                state.slots.retain(|_, slot| !slot.handlers.is_empty());
+                if state.slots.is_empty() {
+                    guard.take();
+                }
+                return Err(e);
+            }
+
             for &type_ in types {
                 state
                     .slots
-                    .entry(type_)
-                    .or_insert_with(|| Slot::new(type_))
+                    .get_mut(&type_)
+                    .unwrap()
                     .handlers
                     .push(handler.clone());
             }
         }
 
-        Self { types, handler }
+        Ok(Self { types, handler })
     }
     /// Forget this guard, leaving the shutdown handler installed for the
     /// lifetime of the program.
@@ -187,24 +405,26 @@ impl<'a> Drop for ShutdownGuard<'a> {
         if !self.types.is_empty() {
             let ptr = Arc::as_ptr(&self.handler) as *const ();
             let mut guard = STATE.lock();
-            let state = guard.as_mut().expect("State should be initialized");
-            for &type_ in self.types {
-                if let Entry::Occupied(mut occ) = state.slots.entry(type_) {
-                    let handlers = &mut occ.get_mut().handlers;
-                    let (index, _) = handlers
-                        .iter()
-                        .enumerate()
-                        .rev()
-                        .find(|&(_, item)| Arc::as_ptr(item) as *const () == ptr)
-                        .expect("State should contain handler");
-                    handlers.remove(index);
-                    if handlers.is_empty() {
-                        occ.remove();
+            if let Some(state) = guard.as_mut() {
+                for &type_ in self.types {
+                    if let Entry::Occupied(mut occ) = state.slots.entry(type_) {
+                        let handlers = &mut occ.get_mut().handlers;
+                        if let Some((index, _)) = handlers
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .find(|&(_, item)| Arc::as_ptr(item) as *const () == ptr)
+                        {
+                            handlers.remove(index);
+                        }
+                        if handlers.is_empty() {
+                            occ.remove();
+                        }
                     }
                 }
-            }
-            if state.slots.is_empty() {
-                guard.take();
+                if state.slots.is_empty() {
+                    guard.take();
+                }
             }
         }
     }
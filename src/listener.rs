@@ -0,0 +1,260 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{self, UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+
+use super::unix::{cloexec_pipe, Fd};
+use super::{Error, ShutdownGuard, ShutdownType};
+
+/// Implemented for listeners which can be wrapped by a `GracefulListener`.
+pub trait Listen: AsRawFd {
+    /// The type of accepted connections.
+    type Stream;
+    /// The type of peer addresses.
+    type Addr;
+
+    /// Accept a new connection.
+    fn accept(&self) -> io::Result<(Self::Stream, Self::Addr)>;
+    /// Move the listener into or out of non-blocking mode.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+    /// Move an accepted connection into blocking mode, since on some
+    /// platforms it inherits the mode of the listener.
+    fn set_blocking(stream: &Self::Stream) -> io::Result<()>;
+}
+
+impl Listen for TcpListener {
+    type Stream = TcpStream;
+    type Addr = SocketAddr;
+
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+    fn set_blocking(stream: &TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)
+    }
+}
+
+impl Listen for UnixListener {
+    type Stream = UnixStream;
+    type Addr = net::SocketAddr;
+
+    fn accept(&self) -> io::Result<(UnixStream, net::SocketAddr)> {
+        UnixListener::accept(self)
+    }
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+    fn set_blocking(stream: &UnixStream) -> io::Result<()> {
+        stream.set_nonblocking(false)
+    }
+}
+
+struct Shared {
+    shutdown: AtomicBool,
+    // Becomes readable once shutdown begins, waking any blocked `accept`
+    wake: (Fd, Fd),
+    active: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl Shared {
+    fn shutdown(&self) {
+        if !self.shutdown.swap(true, Ordering::Relaxed) {
+            unsafe {
+                libc::write(self.wake.1 .0, b"1".as_ptr() as *const _, 1);
+            }
+        }
+    }
+}
+
+/// Held by each connection accepted via a `GracefulListener`. The
+/// connection is considered closed once its token is dropped.
+#[derive(Debug)]
+pub struct DrainToken {
+    shared: Arc<Shared>,
+}
+
+impl DrainToken {
+    /// Returns `true` once the listener has begun shutting down, in which
+    /// case the connection should be closed at the next opportunity.
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for DrainToken {
+    fn drop(&mut self) {
+        let mut active = self.shared.active.lock();
+        *active -= 1;
+        if *active == 0 {
+            self.shared.closed.notify_all();
+        }
+    }
+}
+
+impl std::fmt::Debug for Shared {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Shared")
+            .field("shutdown", &self.shutdown)
+            .finish()
+    }
+}
+
+/// Wraps a listener so that accepting connections stops cleanly when a
+/// shutdown is requested, and so that the server can wait for existing
+/// connections to close before exiting.
+///
+/// The shutdown handler installed by the listener takes priority over any
+/// existing `ShutdownGuard` for the same shutdown types, in the same way
+/// as if a new `ShutdownGuard` was created.
+pub struct GracefulListener<L> {
+    listener: L,
+    shared: Arc<Shared>,
+    guard: Option<ShutdownGuard<'static>>,
+}
+
+impl<L: Listen> GracefulListener<L> {
+    /// Stop accepting connections on an interrupt or terminate request.
+    pub fn new(listener: L) -> Result<Self, Error> {
+        Self::with_types(
+            listener,
+            &[ShutdownType::Interrupt, ShutdownType::Terminate],
+        )
+    }
+
+    /// Stop accepting connections when one of the given kinds of shutdown
+    /// is requested. If `types` is empty, no shutdown handler is installed,
+    /// and the listener only stops when `shutdown` is called.
+    pub fn with_types(listener: L, types: &'static [ShutdownType]) -> Result<Self, Error> {
+        let wake = cloexec_pipe().map_err(Error::Os)?;
+        let shared = Arc::new(Shared {
+            shutdown: AtomicBool::new(false),
+            wake,
+            active: Mutex::new(0),
+            closed: Condvar::new(),
+        });
+        listener.set_nonblocking(true).map_err(Error::Os)?;
+
+        let handler_shared = shared.clone();
+        let guard = ShutdownGuard::try_new(types, move |_| handler_shared.shutdown())?;
+        Ok(Self {
+            listener,
+            shared,
+            guard: Some(guard),
+        })
+    }
+
+    /// Accept a new connection, blocking until one arrives. Returns `None`
+    /// once shutdown has begun.
+    #[allow(clippy::type_complexity)]
+    pub fn accept(&self) -> io::Result<Option<(L::Stream, L::Addr, DrainToken)>> {
+        let mut pollfds = [
+            libc::pollfd {
+                fd: self.listener.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.shared.wake.0 .0,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        loop {
+            if self.is_shutdown() {
+                return Ok(None);
+            }
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    L::set_blocking(&stream)?;
+                    *self.shared.active.lock() += 1;
+                    let token = DrainToken {
+                        shared: self.shared.clone(),
+                    };
+                    return Ok(Some((stream, addr, token)));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+            if unsafe { libc::poll(pollfds.as_mut_ptr(), 2, -1) } < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Iterate over incoming connections until shutdown begins.
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<(L::Stream, DrainToken)>> + '_ {
+        std::iter::from_fn(move || match self.accept() {
+            Ok(Some((stream, _, token))) => Some(Ok((stream, token))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        })
+    }
+
+    /// Begin shutting down, as though a shutdown had been requested.
+    pub fn shutdown(&self) {
+        self.shared.shutdown();
+    }
+
+    /// Returns `true` once shutdown has begun.
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of accepted connections whose `DrainToken` has
+    /// not yet been dropped.
+    pub fn active_connections(&self) -> usize {
+        *self.shared.active.lock()
+    }
+
+    /// Wait until every accepted connection has been closed, or until
+    /// `timeout` elapses. Returns `true` if every connection was closed.
+    pub fn wait_connections_closed(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut active = self.shared.active.lock();
+        while *active > 0 {
+            if self
+                .shared
+                .closed
+                .wait_until(&mut active, deadline)
+                .timed_out()
+            {
+                return *active == 0;
+            }
+        }
+        true
+    }
+
+    /// Access the underlying listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+
+    /// Stop intercepting shutdown requests, and return the underlying
+    /// listener, which is left in non-blocking mode.
+    pub fn into_inner(mut self) -> L {
+        self.guard.take();
+        self.listener
+    }
+}
+
+impl<L: std::fmt::Debug> std::fmt::Debug for GracefulListener<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GracefulListener")
+            .field("listener", &self.listener)
+            .field("shared", &self.shared)
+            .finish()
+    }
+}
@@ -0,0 +1,71 @@
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+
+use super::{request_nowait, ShutdownType};
+
+static TRIGGERED: AtomicBool = AtomicBool::new(false);
+static MESSAGE: Mutex<Option<String>> = Mutex::const_new(RawMutex::INIT, None);
+
+/// Request a shutdown of the given type when any thread panics.
+///
+/// The previously installed panic hook still runs first, so the panic is
+/// reported as usual. Only the first panic requests a shutdown, and its
+/// message can be retrieved from the shutdown handler via `panic_message`.
+///
+/// The panicking thread never waits for a `ShutdownShield` to be released;
+/// the request is delivered once they have been, as usual.
+pub fn shutdown_on_panic(type_: ShutdownType) {
+    install(None, type_);
+}
+
+/// Request a shutdown of the given type when a thread with one of the
+/// given names panics. The main thread is named `main`. See
+/// `shutdown_on_panic`.
+pub fn shutdown_on_panic_in(threads: &[&str], type_: ShutdownType) {
+    install(
+        Some(threads.iter().map(|&name| name.to_owned()).collect()),
+        type_,
+    );
+}
+
+/// Returns the message of the panic which caused a shutdown to be
+/// requested, if any.
+pub fn panic_message() -> Option<String> {
+    MESSAGE.lock().clone()
+}
+
+fn install(threads: Option<Vec<String>>, type_: ShutdownType) {
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        prev(info);
+
+        if let Some(threads) = &threads {
+            let current = thread::current();
+            match current.name() {
+                Some(name) if threads.iter().any(|t| t == name) => {}
+                _ => return,
+            }
+        }
+        if TRIGGERED.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let payload = info.payload();
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+        *MESSAGE.lock() = Some(match info.location() {
+            Some(location) => format!("{} at {}", message, location),
+            None => message.to_owned(),
+        });
+        // The panicking thread may hold a shield, or others may, so the
+        // request must not wait for them to be released.
+        request_nowait(type_);
+    }));
+}
@@ -0,0 +1,94 @@
+#[cfg(not(target_os = "linux"))]
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use std::io;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+use libc::c_int;
+#[cfg(target_os = "linux")]
+use signal_stack::SignalHandlerGuard;
+
+use super::unix::notify;
+#[cfg(target_os = "linux")]
+use super::unix::single_signal;
+#[cfg(not(target_os = "linux"))]
+use super::worker::Worker;
+use super::{Error, ShutdownType};
+
+// The signal requested via `PR_SET_PDEATHSIG`. Real-time signals at the
+// top of the range are rarely used by other libraries.
+#[cfg(target_os = "linux")]
+pub(crate) fn signal() -> c_int {
+    libc::SIGRTMAX()
+}
+
+/// Delivers a `ShutdownType::ParentExited` request when the parent process
+/// exits, using `PR_SET_PDEATHSIG`.
+#[cfg(target_os = "linux")]
+pub struct ParentWatch {
+    guard: Option<SignalHandlerGuard<'static>>,
+}
+
+#[cfg(target_os = "linux")]
+impl ParentWatch {
+    pub fn new() -> Result<Self, Error> {
+        let ppid = unsafe { libc::getppid() };
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                single_signal(signal())?,
+                Arc::new(|_| {
+                    notify(ShutdownType::ParentExited);
+                    true
+                }),
+            )?
+        };
+        let res = Self { guard: Some(guard) };
+        if unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal() as libc::c_ulong, 0, 0, 0) } != 0 {
+            return Err(Error::Os(io::Error::last_os_error()));
+        }
+        // The parent may have exited before the death signal was requested
+        if unsafe { libc::getppid() } != ppid {
+            notify(ShutdownType::ParentExited);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for ParentWatch {
+    fn drop(&mut self) {
+        unsafe {
+            libc::prctl(libc::PR_SET_PDEATHSIG, 0, 0, 0, 0);
+        }
+        self.guard.take();
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Delivers a `ShutdownType::ParentExited` request when the parent process
+/// exits, by periodically checking whether this process has been
+/// re-parented.
+#[cfg(not(target_os = "linux"))]
+pub struct ParentWatch {
+    _worker: Worker,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl ParentWatch {
+    pub fn new() -> Result<Self, Error> {
+        let ppid = unsafe { libc::getppid() };
+        let worker = Worker::every("grace-parent", POLL_INTERVAL, move || {
+            if unsafe { libc::getppid() } != ppid {
+                notify(ShutdownType::ParentExited);
+                return false;
+            }
+            true
+        })?;
+        Ok(Self { _worker: worker })
+    }
+}
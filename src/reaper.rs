@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+
+use parking_lot::Mutex;
+use signal_stack::SignalHandlerGuard;
+
+use super::worker::{Wakeup, Worker};
+use super::Error;
+
+type Callback = Box<dyn FnOnce(ExitStatus) + Send>;
+
+// Limits how many exit statuses are retained for orphans which nobody
+// has asked about.
+const MAX_UNCLAIMED: usize = 1024;
+
+// Set whilst a reaper exists.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+// Marks a reaper as existing, for as long as it is not dropped.
+struct Active;
+
+impl Active {
+    fn acquire() -> Result<Self, Error> {
+        if ACTIVE.swap(true, Ordering::SeqCst) {
+            return Err(Error::Os(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a child reaper already exists",
+            )));
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        ACTIVE.store(false, Ordering::SeqCst);
+    }
+}
+
+#[derive(Default)]
+struct ReaperState {
+    watched: HashMap<libc::pid_t, Vec<Callback>>,
+    excluded: HashSet<libc::pid_t>,
+    unclaimed: VecDeque<(libc::pid_t, ExitStatus)>,
+}
+
+struct Shared {
+    wakeup: Arc<Wakeup>,
+    reap_orphans: bool,
+    state: Mutex<ReaperState>,
+}
+
+fn try_wait(pid: libc::pid_t) -> io::Result<Option<(libc::pid_t, ExitStatus)>> {
+    let mut status = 0;
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+            0 => return Ok(None),
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            pid => return Ok(Some((pid, ExitStatus::from_raw(status)))),
+        }
+    }
+}
+
+// Lists the direct children of this process, if the platform allows it.
+#[cfg(target_os = "linux")]
+fn children() -> Option<Vec<libc::pid_t>> {
+    let mut res = Vec::new();
+    for entry in std::fs::read_dir("/proc/self/task").ok()? {
+        let contents = std::fs::read_to_string(entry.ok()?.path().join("children")).ok()?;
+        res.extend(
+            contents
+                .split_whitespace()
+                .filter_map(|pid| pid.parse::<libc::pid_t>().ok()),
+        );
+    }
+    Some(res)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn children() -> Option<Vec<libc::pid_t>> {
+    None
+}
+
+// Returns the process ID of a child which has exited, without reaping it.
+fn peek_exited() -> Option<libc::pid_t> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let options = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    while unsafe { libc::waitid(libc::P_ALL, 0, &mut info, options) } != 0 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return None;
+        }
+    }
+    match unsafe { info.si_pid() } {
+        0 => None,
+        pid => Some(pid),
+    }
+}
+
+impl Shared {
+    fn reap(&self) {
+        let mut ready = Vec::new();
+        {
+            let mut state = self.state.lock();
+            let watched: Vec<_> = state.watched.keys().copied().collect();
+            for pid in watched {
+                match try_wait(pid) {
+                    Ok(Some((_, status))) => {
+                        ready.push((state.watched.remove(&pid).unwrap(), status));
+                    }
+                    Ok(None) => {}
+                    // Not our child, or already reaped elsewhere
+                    Err(_) => {
+                        state.watched.remove(&pid);
+                    }
+                }
+            }
+
+            if self.reap_orphans {
+                let mut reaped = Vec::new();
+                if let Some(pids) = children() {
+                    for pid in pids {
+                        if !state.excluded.contains(&pid) && !state.watched.contains_key(&pid) {
+                            if let Ok(Some(item)) = try_wait(pid) {
+                                reaped.push(item);
+                            }
+                        }
+                    }
+                } else {
+                    // We can't tell which children exist, so reap exited
+                    // children one at a time, stopping at an excluded one.
+                    while let Some(pid) = peek_exited() {
+                        if state.excluded.contains(&pid) {
+                            break;
+                        }
+                        match try_wait(pid) {
+                            Ok(Some(item)) => reaped.push(item),
+                            _ => break,
+                        }
+                    }
+                }
+                for (pid, status) in reaped {
+                    if let Some(callbacks) = state.watched.remove(&pid) {
+                        ready.push((callbacks, status));
+                    } else {
+                        if state.unclaimed.len() >= MAX_UNCLAIMED {
+                            state.unclaimed.pop_front();
+                        }
+                        state.unclaimed.push_back((pid, status));
+                    }
+                }
+            }
+        }
+
+        // Run callbacks without holding the lock, in case they call back into us
+        for (callbacks, status) in ready {
+            for callback in callbacks {
+                callback(status);
+            }
+        }
+    }
+
+    fn run(&self) {
+        loop {
+            self.reap();
+            if !self.wakeup.wait() {
+                break;
+            }
+        }
+    }
+}
+
+/// Reaps exited child processes in response to `SIGCHLD`, and dispatches
+/// their exit statuses to callbacks or channels.
+///
+/// Child processes are reaped on a background thread, which is stopped
+/// when the reaper is dropped. Only one reaper may exist at a time, and
+/// creating another whilst it exists fails.
+///
+/// By default, only children which have been explicitly registered via
+/// `watch` are reaped, so the reaper does not conflict with
+/// `std::process::Child::wait` for other children. A reaper constructed via
+/// `orphans` or `subreaper` will also reap every other child except those
+/// registered via `exclude`, which is useful when running as an init process.
+pub struct ChildReaper {
+    shared: Arc<Shared>,
+    _guard: SignalHandlerGuard<'static>,
+    _worker: Worker,
+    _active: Active,
+}
+
+impl ChildReaper {
+    /// Reap only those children which are registered via `watch`.
+    pub fn new() -> Result<Self, Error> {
+        Self::new_inner(false)
+    }
+
+    /// Reap every child process except those registered via `exclude`.
+    ///
+    /// The exit statuses of children which have not been registered via
+    /// `watch` are retained, so that a subsequent call to `watch` for that
+    /// child will still receive its exit status.
+    ///
+    /// A child spawned whilst this reaper exists may be reaped before it is
+    /// passed to `exclude`, in which case `std::process::Child::wait` will
+    /// fail. Spawn such children before creating the reaper, or use `watch`
+    /// instead of waiting on them directly.
+    ///
+    /// Where the children of this process cannot be listed (other than on
+    /// Linux), an excluded child which has exited but has not yet been
+    /// waited on prevents other children from being reaped, until it has
+    /// been waited on and another `SIGCHLD` is received.
+    pub fn orphans() -> Result<Self, Error> {
+        Self::new_inner(true)
+    }
+
+    /// Mark this process as a child subreaper (`PR_SET_CHILD_SUBREAPER`), so
+    /// that orphaned descendants are re-parented to this process rather than
+    /// to init, and then reap them in the same way as `orphans`.
+    #[cfg(target_os = "linux")]
+    pub fn subreaper() -> Result<Self, Error> {
+        let reaper = Self::new_inner(true)?;
+        if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+            return Err(Error::Os(io::Error::last_os_error()));
+        }
+        Ok(reaper)
+    }
+
+    fn new_inner(reap_orphans: bool) -> Result<Self, Error> {
+        let active = Active::acquire()?;
+        let shared = Arc::new(Shared {
+            wakeup: Wakeup::new()?,
+            reap_orphans,
+            state: Mutex::new(ReaperState::default()),
+        });
+
+        let thread_shared = shared.clone();
+        let worker = Worker::spawn("grace-reaper", &shared.wakeup, move || thread_shared.run())?;
+
+        let wakeup = shared.wakeup.clone();
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                &[libc::SIGCHLD],
+                Arc::new(move |_| {
+                    wakeup.wake();
+                    // Allow other handlers to observe the signal too
+                    false
+                }),
+            )?
+        };
+
+        Ok(Self {
+            shared,
+            _guard: guard,
+            _worker: worker,
+            _active: active,
+        })
+    }
+
+    /// Call `callback` with the exit status of the child process `pid` once
+    /// it exits. The callback runs on the reaper's background thread.
+    pub fn watch<F: FnOnce(ExitStatus) + Send + 'static>(&self, pid: u32, callback: F) {
+        let pid = pid as libc::pid_t;
+        let mut state = self.shared.state.lock();
+        if let Some(index) = state.unclaimed.iter().position(|&(p, _)| p == pid) {
+            let (_, status) = state.unclaimed.remove(index).unwrap();
+            drop(state);
+            callback(status);
+            return;
+        }
+        state.excluded.remove(&pid);
+        state
+            .watched
+            .entry(pid)
+            .or_default()
+            .push(Box::new(callback));
+        drop(state);
+
+        // The child may already have exited
+        self.shared.wakeup.wake();
+    }
+
+    /// Send the exit status of the child process `pid` on a channel once it
+    /// exits. If the child cannot be waited on, the channel is closed.
+    pub fn watch_channel(&self, pid: u32) -> mpsc::Receiver<ExitStatus> {
+        let (tx, rx) = mpsc::channel();
+        self.watch(pid, move |status| {
+            let _ = tx.send(status);
+        });
+        rx
+    }
+
+    /// Never reap the child process `pid`, so that it can be waited on
+    /// by other means, such as `std::process::Child::wait`.
+    ///
+    /// This only takes effect for children which have not already been
+    /// reaped. See `orphans`.
+    pub fn exclude(&self, pid: u32) {
+        self.shared.state.lock().excluded.insert(pid as libc::pid_t);
+    }
+}
+
+impl std::fmt::Debug for ChildReaper {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChildReaper")
+            .field("reap_orphans", &self.shared.reap_orphans)
+            .finish()
+    }
+}
@@ -0,0 +1,183 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+use signal_stack::SignalHandlerGuard;
+
+use super::unix::Intercept;
+use super::worker::{Registry, Wakeup, Worker};
+use super::Error;
+
+/// The error type returned by reload handlers.
+pub type ReloadError = Box<dyn std::error::Error + Send + Sync>;
+
+/// This trait is implemented for functions which match the required signature
+/// for reload handlers.
+///
+/// The handler will be called on a background thread, so does not need to be
+/// async-signal-safe. Returning an error (or panicking) marks the reload as
+/// failed in the `reload_status()`.
+pub trait ReloadHandler: FnMut() -> Result<(), ReloadError> + Send + 'static {}
+impl<T: FnMut() -> Result<(), ReloadError> + Send + 'static> ReloadHandler for T {}
+
+/// The outcome of reload requests so far, as returned by `reload_status()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReloadStatus {
+    /// The number of reload requests (`SIGHUP`s) received.
+    pub requested: u64,
+    /// The number of reloads which succeeded. Bursts of requests are
+    /// coalesced, so this may be less than `requested`.
+    pub succeeded: u64,
+    /// The number of reloads which failed.
+    pub failed: u64,
+    /// Whether a reload is currently in progress.
+    pub in_progress: bool,
+    /// When the most recent reload finished.
+    pub last_finished: Option<SystemTime>,
+    /// The error from the most recent reload, if it failed.
+    pub last_error: Option<String>,
+}
+
+static REQUESTED: AtomicUsize = AtomicUsize::new(0);
+static STATUS: Mutex<ReloadStatus> = Mutex::const_new(
+    RawMutex::INIT,
+    ReloadStatus {
+        requested: 0,
+        succeeded: 0,
+        failed: 0,
+        in_progress: false,
+        last_finished: None,
+        last_error: None,
+    },
+);
+static STATE: Registry<State, SharedHandler> = Registry::new();
+
+/// Returns the outcome of reload requests so far.
+pub fn reload_status() -> ReloadStatus {
+    let mut status = STATUS.lock().clone();
+    status.requested = REQUESTED.load(Ordering::Relaxed) as u64;
+    status
+}
+
+type SharedHandler = Mutex<dyn ReloadHandler>;
+
+struct State {
+    _worker: Worker,
+    _guard: SignalHandlerGuard<'static>,
+    _intercept: Intercept,
+}
+
+impl State {
+    fn new() -> Result<Self, Error> {
+        let wakeup = Wakeup::new()?;
+        let pending = Arc::new(AtomicBool::new(false));
+
+        let thread_wakeup = wakeup.clone();
+        let thread_pending = pending.clone();
+        let worker = Worker::spawn("grace-reload", &wakeup, move || {
+            while thread_wakeup.wait() {
+                // Any requests which arrive during the reload result in
+                // exactly one further reload.
+                if thread_pending.swap(false, Ordering::Relaxed) {
+                    reload();
+                }
+            }
+        })?;
+
+        let intercept = Intercept::new(libc::SIGHUP);
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                &[libc::SIGHUP],
+                Arc::new(move |_| {
+                    REQUESTED.fetch_add(1, Ordering::Relaxed);
+                    pending.store(true, Ordering::Relaxed);
+                    wakeup.wake();
+                    true
+                }),
+            )?
+        };
+
+        Ok(Self {
+            _worker: worker,
+            _guard: guard,
+            _intercept: intercept,
+        })
+    }
+}
+
+fn reload() {
+    let handler = match STATE.items().pop() {
+        Some(handler) => handler,
+        None => return,
+    };
+
+    STATUS.lock().in_progress = true;
+    #[cfg(feature = "systemd")]
+    let _ = super::systemd::notify("RELOADING=1");
+
+    let res = match catch_unwind(AssertUnwindSafe(|| (handler.lock())())) {
+        Ok(res) => res.map_err(|e| e.to_string()),
+        Err(_) => Err("reload handler panicked".into()),
+    };
+
+    #[cfg(feature = "systemd")]
+    let _ = super::systemd::ready();
+    let mut status = STATUS.lock();
+    status.in_progress = false;
+    status.last_finished = Some(SystemTime::now());
+    match res {
+        Ok(()) => {
+            status.succeeded += 1;
+            status.last_error = None;
+        }
+        Err(e) => {
+            status.failed += 1;
+            status.last_error = Some(e);
+        }
+    }
+}
+
+/// Call a user-defined function whenever the process is asked to reload
+/// its configuration (`SIGHUP`), for as long as the guard exists.
+///
+/// Like `ShutdownGuard`, only the most recently created guard is called.
+/// Reload requests which arrive whilst a reload is in progress are
+/// coalesced into a single further reload.
+///
+/// Whilst no guard exists, `SIGHUP` has its default behaviour.
+pub struct ReloadGuard {
+    handler: Arc<SharedHandler>,
+}
+
+impl ReloadGuard {
+    /// Call a user-defined function whenever a reload is requested.
+    ///
+    /// # Panics
+    /// Panics if the reload handler could not be installed. See `try_new`.
+    pub fn new<H: ReloadHandler>(handler: H) -> Self {
+        Self::try_new(handler).expect("Failed to install reload handler")
+    }
+    /// Call a user-defined function whenever a reload is requested,
+    /// returning an error if the reload handler could not be installed.
+    pub fn try_new<H: ReloadHandler>(handler: H) -> Result<Self, Error> {
+        let handler: Arc<SharedHandler> = Arc::new(Mutex::new(handler));
+        STATE.add(handler.clone(), State::new)?;
+        Ok(Self { handler })
+    }
+}
+
+impl std::fmt::Debug for ReloadGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadGuard").finish()
+    }
+}
+
+impl Drop for ReloadGuard {
+    fn drop(&mut self) {
+        STATE.remove(&self.handler);
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::RawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use super::unix::{cloexec_pipe, Fd};
+use super::worker::Worker;
+use super::{request_shutdown, Error, GracefulListener, ShutdownType};
+
+// Limits how long a control socket client may take to send its command.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn parse_command(command: &str) -> Option<ShutdownType> {
+    match command.trim() {
+        "shutdown" | "terminate" => Some(ShutdownType::Terminate),
+        "interrupt" => Some(ShutdownType::Interrupt),
+        _ => None,
+    }
+}
+
+fn handle_client(stream: UnixStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    match parse_command(&line) {
+        Some(type_) => {
+            (&stream).write_all(b"ok\n")?;
+            request_shutdown(type_);
+        }
+        None => (&stream).write_all(b"error: unknown command\n")?,
+    }
+    Ok(())
+}
+
+// Wait until `fd` is hung up, or until `wake` becomes readable. Returns
+// `true` if `fd` was hung up, or `false` if woken or if `fd` is closed.
+fn wait_hangup(fd: RawFd, wake: RawFd) -> bool {
+    // Requesting no events means data waiting to be read is ignored, but
+    // hang-ups and errors are always reported.
+    let mut pollfds = [
+        libc::pollfd {
+            fd,
+            events: 0,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: wake,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        if unsafe { libc::poll(pollfds.as_mut_ptr(), 2, -1) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return false;
+        }
+        if pollfds[1].revents != 0 {
+            return false;
+        }
+        if pollfds[0].revents & libc::POLLNVAL != 0 {
+            return false;
+        }
+        if pollfds[0].revents & (libc::POLLHUP | libc::POLLERR) != 0 {
+            return true;
+        }
+    }
+}
+
+// Returns a random duration between zero and `max`, so that many processes
+// started at the same time do not all shut down at the same time.
+fn jitter(max: Duration) -> Duration {
+    // `RandomState` is randomly seeded, so this is good enough here
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    let fraction = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+    max.mul_f64(fraction)
+}
+
+// The read end becomes readable once woken.
+struct Pipe(Fd, Fd);
+
+impl Pipe {
+    fn new() -> io::Result<Self> {
+        let (read, write) = cloexec_pipe()?;
+        Ok(Self(read, write))
+    }
+    fn wake(&self) {
+        unsafe {
+            libc::write(self.1 .0, b"1".as_ptr() as *const _, 1);
+        }
+    }
+}
+
+/// A source of shutdown requests other than signals, which delivers
+/// requests to `ShutdownGuard` handlers in exactly the same way as the
+/// corresponding signal would, for as long as it exists.
+///
+/// Each source runs on its own background thread, which is stopped when
+/// the source is dropped. Custom sources can be implemented using
+/// `request_shutdown`.
+pub struct ShutdownSource {
+    _worker: Worker,
+}
+
+impl ShutdownSource {
+    fn spawn<S, F>(name: &str, stop: S, f: F) -> Result<Self, Error>
+    where
+        S: FnOnce() + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        Ok(Self {
+            _worker: Worker::with_stop(name, stop, f)?,
+        })
+    }
+
+    /// Request a shutdown of the given type once standard input is closed
+    /// by the other end, eg. when a parent process closes its end of the
+    /// pipe.
+    ///
+    /// Standard input is not read from, so this does not interfere with
+    /// the program's own use of it. Only pipes and sockets report being
+    /// closed in this way.
+    ///
+    /// Fails if standard input is not open, as is common for daemons.
+    pub fn stdin_eof(type_: ShutdownType) -> Result<Self, Error> {
+        if unsafe { libc::fcntl(libc::STDIN_FILENO, libc::F_GETFD) } < 0 {
+            return Err(Error::Os(io::Error::last_os_error()));
+        }
+        let pipe = Arc::new(Pipe::new().map_err(Error::Os)?);
+        let thread_pipe = pipe.clone();
+        Self::spawn(
+            "grace-stdin",
+            move || pipe.wake(),
+            move || {
+                if wait_hangup(libc::STDIN_FILENO, thread_pipe.0 .0) {
+                    request_shutdown(type_);
+                }
+            },
+        )
+    }
+
+    /// Listen on a unix domain socket at `path` for shutdown commands.
+    ///
+    /// Clients send a single line containing `shutdown` (or `terminate`),
+    /// or `interrupt`, and receive `ok` or an error in response. Access to
+    /// the socket should be controlled via the permissions of its parent
+    /// directory. The socket file is removed when the source is dropped.
+    pub fn control_socket<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let listener = UnixListener::bind(&path).map_err(Error::Os)?;
+        let listener = Arc::new(GracefulListener::with_types(listener, &[])?);
+        let thread_listener = listener.clone();
+        Self::spawn(
+            "grace-control",
+            move || {
+                listener.shutdown();
+                let _ = fs::remove_file(&path);
+            },
+            move || {
+                for (stream, _token) in thread_listener.incoming().flatten() {
+                    let _ = handle_client(stream);
+                }
+            },
+        )
+    }
+
+    /// Poll for the existence of a file at `path` every `interval`. When it
+    /// appears, it is removed and a shutdown is requested. If the file
+    /// contains `interrupt`, an interrupt is requested, otherwise a
+    /// terminate request.
+    pub fn trigger_file<P: AsRef<Path>>(path: P, interval: Duration) -> Result<Self, Error> {
+        let path: PathBuf = path.as_ref().to_path_buf();
+        let worker = Worker::every("grace-trigger", interval, move || {
+            if let Ok(contents) = fs::read_to_string(&path) {
+                let _ = fs::remove_file(&path);
+                request_shutdown(parse_command(&contents).unwrap_or(ShutdownType::Terminate));
+            }
+            true
+        })?;
+        Ok(Self { _worker: worker })
+    }
+
+    /// Request termination once `lifetime` has elapsed, plus a random
+    /// amount of time up to `max_jitter`. This is useful to periodically
+    /// recycle long-running worker processes.
+    pub fn after(lifetime: Duration, max_jitter: Duration) -> Result<Self, Error> {
+        let timeout = lifetime + jitter(max_jitter);
+        let worker = Worker::every("grace-timer", timeout, || {
+            request_shutdown(ShutdownType::Terminate);
+            false
+        })?;
+        Ok(Self { _worker: worker })
+    }
+
+    /// Request termination at the scheduled time `at`, plus a random amount
+    /// of time up to `max_jitter`. If `at` is in the past, termination is
+    /// requested immediately.
+    ///
+    /// The delay is calculated when this is called, so later changes to
+    /// the system clock are not taken into account.
+    pub fn at(at: SystemTime, max_jitter: Duration) -> Result<Self, Error> {
+        let lifetime = at.duration_since(SystemTime::now()).unwrap_or_default();
+        Self::after(lifetime, max_jitter)
+    }
+}
+
+impl std::fmt::Debug for ShutdownSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ShutdownSource").finish()
+    }
+}
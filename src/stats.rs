@@ -0,0 +1,89 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::ShutdownType;
+
+const ALL_TYPES: &[ShutdownType] = &[
+    ShutdownType::Interrupt,
+    ShutdownType::Terminate,
+    ShutdownType::ParentExited,
+];
+
+/// Statistics for a single kind of shutdown request, as returned by
+/// `statistics()`.
+///
+/// Each counter only ever increases, so these are suitable for exporting
+/// as eg. Prometheus counters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ShutdownStatistics {
+    /// The kind of shutdown request.
+    pub type_: ShutdownType,
+    /// The number of shutdown requests received.
+    pub received: u64,
+    /// The number of shutdown requests passed to a shutdown handler.
+    pub handled: u64,
+    /// The number of shutdown requests received when no shutdown handler
+    /// was installed, causing the process to exit.
+    pub unhandled: u64,
+    /// The number of shutdown requests which were not passed to a shutdown
+    /// handler because of its `Delivery` mode.
+    pub suppressed: u64,
+}
+
+pub(crate) enum Outcome {
+    Handled,
+    Unhandled,
+    Suppressed,
+}
+
+struct Counters {
+    received: AtomicUsize,
+    handled: AtomicUsize,
+    unhandled: AtomicUsize,
+    suppressed: AtomicUsize,
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const ZERO: Counters = Counters {
+    received: AtomicUsize::new(0),
+    handled: AtomicUsize::new(0),
+    unhandled: AtomicUsize::new(0),
+    suppressed: AtomicUsize::new(0),
+};
+static COUNTERS: [Counters; 3] = [ZERO; 3];
+
+fn counters(type_: ShutdownType) -> &'static Counters {
+    &COUNTERS[match type_ {
+        ShutdownType::Interrupt => 0,
+        ShutdownType::Terminate => 1,
+        ShutdownType::ParentExited => 2,
+    }]
+}
+
+pub(crate) fn record(type_: ShutdownType, outcome: Outcome, count: usize) {
+    let counters = counters(type_);
+    counters.received.fetch_add(count, Ordering::Relaxed);
+    match outcome {
+        Outcome::Handled => &counters.handled,
+        Outcome::Unhandled => &counters.unhandled,
+        Outcome::Suppressed => &counters.suppressed,
+    }
+    .fetch_add(count, Ordering::Relaxed);
+}
+
+/// Returns statistics for every kind of shutdown request.
+pub fn statistics() -> Vec<ShutdownStatistics> {
+    ALL_TYPES
+        .iter()
+        .map(|&type_| {
+            let counters = counters(type_);
+            ShutdownStatistics {
+                type_,
+                received: counters.received.load(Ordering::Relaxed) as u64,
+                handled: counters.handled.load(Ordering::Relaxed) as u64,
+                unhandled: counters.unhandled.load(Ordering::Relaxed) as u64,
+                suppressed: counters.suppressed.load(Ordering::Relaxed) as u64,
+            }
+        })
+        .collect()
+}
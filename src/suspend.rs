@@ -0,0 +1,157 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use signal_stack::{BlockSignalsGuard, SigSet, SignalHandlerGuard};
+
+use super::worker::{Registry, Wakeup, Worker};
+use super::Error;
+
+struct Callbacks {
+    on_suspend: Box<dyn FnMut() + Send>,
+    on_resume: Box<dyn FnMut() + Send>,
+}
+
+static STATE: Registry<State, Mutex<Callbacks>> = Registry::new();
+
+struct Shared {
+    wakeup: Arc<Wakeup>,
+    pending: AtomicBool,
+    // Set whilst the background thread re-raises `SIGTSTP`, so that it is
+    // passed on to the default action.
+    stopping: AtomicBool,
+}
+
+struct State {
+    _worker: Worker,
+    _guard: SignalHandlerGuard<'static>,
+}
+
+impl State {
+    fn new() -> Result<Self, Error> {
+        let shared = Arc::new(Shared {
+            wakeup: Wakeup::new()?,
+            pending: AtomicBool::new(false),
+            stopping: AtomicBool::new(false),
+        });
+
+        let thread_shared = shared.clone();
+        let worker = Worker::spawn("grace-suspend", &shared.wakeup, move || {
+            background_thread(&thread_shared)
+        })?;
+
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                &[libc::SIGTSTP],
+                Arc::new(move |_| {
+                    if shared.stopping.swap(false, Ordering::SeqCst) {
+                        return false;
+                    }
+                    shared.pending.store(true, Ordering::SeqCst);
+                    shared.wakeup.wake();
+                    true
+                }),
+            )?
+        };
+
+        Ok(Self {
+            _worker: worker,
+            _guard: guard,
+        })
+    }
+}
+
+fn background_thread(shared: &Shared) {
+    // `SIGTSTP` is re-raised on this thread, so must not be blocked here
+    let _unblock = BlockSignalsGuard::unblock(&SigSet::empty().with(libc::SIGTSTP));
+    while shared.wakeup.wait() {
+        if shared.pending.swap(false, Ordering::SeqCst) {
+            suspend(shared);
+        }
+    }
+}
+
+fn suspend(shared: &Shared) {
+    let callbacks = STATE.items();
+    if callbacks.is_empty() {
+        return;
+    }
+
+    for callbacks in callbacks.iter().rev() {
+        let _ = catch_unwind(AssertUnwindSafe(|| (callbacks.lock().on_suspend)()));
+    }
+
+    // Let the signal through to any other handlers, and then to the default
+    // action, which stops the process. This returns once it is continued.
+    shared.stopping.store(true, Ordering::SeqCst);
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+    shared.stopping.store(false, Ordering::SeqCst);
+
+    for callbacks in &callbacks {
+        let _ = catch_unwind(AssertUnwindSafe(|| (callbacks.lock().on_resume)()));
+    }
+}
+
+/// Call user-defined functions when the process is suspended via `SIGTSTP`
+/// (eg. Ctrl + Z), and when it is subsequently resumed, for as long as the
+/// guard exists.
+///
+/// The callbacks run on a background thread, and the process is only
+/// stopped once every `on_suspend` callback has completed. If several
+/// guards exist, `on_suspend` callbacks run in the reverse order to which
+/// the guards were created, and `on_resume` callbacks in the same order.
+///
+/// Other handlers for `SIGTSTP`, such as that of a `TerminalGuard`, run in
+/// the order in which they were installed relative to the first guard:
+/// handlers installed beforehand run after the `on_suspend` callbacks, but
+/// handlers installed afterwards run before them, and then again once the
+/// callbacks have completed. For example, a `TerminalGuard` created after
+/// a `SuspendGuard` restores the terminal before `on_suspend` is called.
+pub struct SuspendGuard {
+    callbacks: Arc<Mutex<Callbacks>>,
+}
+
+impl SuspendGuard {
+    /// Call `on_suspend` before the process is stopped, and `on_resume`
+    /// after it is continued.
+    ///
+    /// # Panics
+    /// Panics if the signal handler could not be installed. See `try_new`.
+    pub fn new<S, R>(on_suspend: S, on_resume: R) -> Self
+    where
+        S: FnMut() + Send + 'static,
+        R: FnMut() + Send + 'static,
+    {
+        Self::try_new(on_suspend, on_resume).expect("Failed to install suspend handler")
+    }
+    /// Call `on_suspend` before the process is stopped, and `on_resume`
+    /// after it is continued, returning an error if the signal handler
+    /// could not be installed.
+    pub fn try_new<S, R>(on_suspend: S, on_resume: R) -> Result<Self, Error>
+    where
+        S: FnMut() + Send + 'static,
+        R: FnMut() + Send + 'static,
+    {
+        let callbacks = Arc::new(Mutex::new(Callbacks {
+            on_suspend: Box::new(on_suspend),
+            on_resume: Box::new(on_resume),
+        }));
+        STATE.add(callbacks.clone(), State::new)?;
+        Ok(Self { callbacks })
+    }
+}
+
+impl std::fmt::Debug for SuspendGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SuspendGuard").finish()
+    }
+}
+
+impl Drop for SuspendGuard {
+    fn drop(&mut self) {
+        STATE.remove(&self.callbacks);
+    }
+}
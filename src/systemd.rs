@@ -0,0 +1,190 @@
+//! Integration with the systemd service manager, via the `sd_notify`
+//! protocol.
+//!
+//! Notifications are sent as datagrams to the unix socket named by the
+//! `$NOTIFY_SOCKET` environment variable. If the variable is not set, the
+//! process is not running under systemd and notifications are silently
+//! discarded.
+//!
+//! Once this feature is enabled, `STOPPING=1` is sent automatically the
+//! first time a `ShutdownGuard` handles a shutdown request, and the service
+//! manager's shutdown timeout is extended (`EXTEND_TIMEOUT_USEC`) for as
+//! long as shutdown handlers are running.
+
+use std::env;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+
+use super::worker::Worker;
+use super::Error;
+
+// How far the shutdown timeout is extended at a time whilst shutdown
+// handlers are running.
+const HANDLER_EXTENSION: Duration = Duration::from_secs(10);
+
+// Whether `STOPPING=1` has been sent. Watchdog pings are sent whilst the lock
+// is held, so that none can follow it.
+static STOPPING: Mutex<bool> = Mutex::const_new(RawMutex::INIT, false);
+
+/// Send a raw notification, such as `"STATUS=Loading data"`, to the service
+/// manager. Multiple assignments may be separated by newlines.
+///
+/// Returns `false` if `$NOTIFY_SOCKET` is not set.
+pub fn notify(state: &str) -> io::Result<bool> {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) if !path.is_empty() => path,
+        _ => return Ok(false),
+    };
+    let socket = UnixDatagram::unbound()?;
+    match path.to_str().and_then(|s| s.strip_prefix('@')) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        Some(name) => {
+            #[cfg(target_os = "android")]
+            use std::os::android::net::SocketAddrExt;
+            #[cfg(target_os = "linux")]
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(state.as_bytes(), &path)?;
+        }
+    }
+    Ok(true)
+}
+
+/// Notify the service manager that startup has finished (`READY=1`).
+pub fn ready() -> io::Result<bool> {
+    notify("READY=1")
+}
+
+/// Notify the service manager that the service is shutting down
+/// (`STOPPING=1`).
+pub fn stopping() -> io::Result<bool> {
+    let mut stopping = STOPPING.lock();
+    *stopping = true;
+    notify("STOPPING=1")
+}
+
+/// Ask the service manager to extend the current startup or shutdown
+/// timeout, so that it expires no sooner than `extension` from now
+/// (`EXTEND_TIMEOUT_USEC`).
+pub fn extend_timeout(extension: Duration) -> io::Result<bool> {
+    notify(&format!("EXTEND_TIMEOUT_USEC={}", extension.as_micros()))
+}
+
+/// Send a keep-alive ping to the service manager (`WATCHDOG=1`).
+pub fn watchdog() -> io::Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// Returns the interval at which the service manager expects watchdog
+/// pings, if the watchdog is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Some(pid) = env::var_os("WATCHDOG_PID") {
+        if pid.to_str()?.parse::<u32>().ok()? != std::process::id() {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
+// Called whenever a shutdown request is handled. The returned worker
+// extends the shutdown timeout until it is dropped, once the handler returns.
+pub(crate) fn on_shutdown() -> Option<Worker> {
+    let mut stopping = STOPPING.lock();
+    if !*stopping {
+        *stopping = true;
+        let _ = notify("STOPPING=1");
+    }
+    drop(stopping);
+    extend_timeout_every("grace-shutdown-timeout", HANDLER_EXTENSION)
+        .ok()
+        .flatten()
+}
+
+// Extend the timeout now, and then at half of `extension` on a background
+// thread, unless notifications cannot be sent.
+fn extend_timeout_every(name: &str, extension: Duration) -> Result<Option<Worker>, Error> {
+    if !matches!(extend_timeout(extension), Ok(true)) {
+        return Ok(None);
+    }
+    Worker::every(name, extension / 2, move || {
+        matches!(extend_timeout(extension), Ok(true))
+    })
+    .map(Some)
+}
+
+/// Sends `WATCHDOG=1` pings to the service manager from a background
+/// thread, until shutdown begins or the guard is dropped.
+///
+/// Pings stop once shutdown begins so that a hung shutdown is detected
+/// by the watchdog.
+pub struct WatchdogGuard {
+    _worker: Option<Worker>,
+}
+
+impl WatchdogGuard {
+    /// Ping at half the interval requested by the service manager. If the
+    /// watchdog is not enabled for this process, this does nothing.
+    pub fn new() -> Result<Self, Error> {
+        match watchdog_interval() {
+            Some(interval) => Self::with_interval(interval / 2),
+            None => Ok(Self { _worker: None }),
+        }
+    }
+
+    /// Ping at the given interval.
+    pub fn with_interval(interval: Duration) -> Result<Self, Error> {
+        // Checking and pinging under the lock ensures that no ping can
+        // follow `STOPPING=1`.
+        let ping = || {
+            let stopping = STOPPING.lock();
+            !*stopping && matches!(watchdog(), Ok(true))
+        };
+        let worker = if ping() {
+            Some(Worker::every("grace-watchdog", interval, ping)?)
+        } else {
+            None
+        };
+        Ok(Self { _worker: worker })
+    }
+}
+
+impl std::fmt::Debug for WatchdogGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchdogGuard").finish()
+    }
+}
+
+/// Repeatedly extends the service manager's shutdown timeout for as long
+/// as the guard exists, so that long-running shutdown work is not killed.
+///
+/// The timeout is extended immediately, and then again at half of
+/// `extension`, so that it never comes within `extension / 2` of expiring.
+pub struct ExtendTimeoutGuard {
+    _worker: Option<Worker>,
+}
+
+impl ExtendTimeoutGuard {
+    /// Extend the timeout by `extension` at a time.
+    pub fn new(extension: Duration) -> Result<Self, Error> {
+        Ok(Self {
+            _worker: extend_timeout_every("grace-extend-timeout", extension)?,
+        })
+    }
+}
+
+impl std::fmt::Debug for ExtendTimeoutGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExtendTimeoutGuard").finish()
+    }
+}
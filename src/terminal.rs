@@ -0,0 +1,216 @@
+use std::cell::UnsafeCell;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+
+use libc::c_int;
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+use signal_stack::SignalHandlerGuard;
+
+use super::exit::atexit;
+use super::unix::{disposition, is_default, is_intercepted};
+use super::Error;
+
+// Signals whose default action terminates the process.
+const TERMINATE_SIGNALS: &[&[c_int]] = &[
+    &[libc::SIGINT],
+    &[libc::SIGTERM],
+    &[libc::SIGHUP],
+    &[libc::SIGQUIT],
+];
+
+// Every terminal guard which exists, so that they can all be restored by a
+// single panic hook and exit hook.
+static GUARDS: Mutex<Vec<Weak<Shared>>> = Mutex::const_new(RawMutex::INIT, Vec::new());
+static HOOKS_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+fn restore_all() {
+    // A panic may occur whilst the lock is held
+    if let Some(guards) = GUARDS.try_lock() {
+        // Restore the oldest last, so that its state is the one which remains
+        for shared in guards.iter().rev().filter_map(Weak::upgrade) {
+            shared.restore();
+        }
+    }
+}
+
+extern "C" fn restore_at_exit() {
+    restore_all();
+}
+
+fn register(shared: &Arc<Shared>) {
+    let mut guards = GUARDS.lock();
+    guards.retain(|item| item.strong_count() > 0);
+    guards.push(Arc::downgrade(shared));
+    drop(guards);
+
+    if !HOOKS_INSTALLED.swap(true, Ordering::SeqCst) {
+        let prev = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Restore first so that the panic message is displayed correctly
+            restore_all();
+            prev(info);
+        }));
+        unsafe {
+            atexit(restore_at_exit);
+        }
+    }
+}
+
+struct Shared {
+    fd: RawFd,
+    original: libc::termios,
+    // The terminal state at the time the process was suspended
+    suspended: UnsafeCell<libc::termios>,
+    is_suspended: AtomicBool,
+    active: AtomicBool,
+}
+
+// The `suspended` state is only accessed from signal handlers, and access
+// is synchronized via `is_suspended`.
+unsafe impl Sync for Shared {}
+unsafe impl Send for Shared {}
+
+impl Shared {
+    // These are all async-signal-safe.
+    fn restore(&self) {
+        if self.active.load(Ordering::Acquire) {
+            unsafe {
+                libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+            }
+        }
+    }
+    fn terminate(&self, signum: c_int) {
+        // If one of our own handlers consumes the signal, the process is not
+        // about to exit.
+        if !is_intercepted(signum) {
+            self.restore();
+        }
+    }
+    fn suspend(&self, _signum: c_int) {
+        if self.active.load(Ordering::Acquire) && !self.is_suspended.load(Ordering::Acquire) {
+            unsafe {
+                if libc::tcgetattr(self.fd, self.suspended.get()) == 0 {
+                    self.is_suspended.store(true, Ordering::Release);
+                }
+            }
+            self.restore();
+        }
+    }
+    fn resume(&self, _signum: c_int) {
+        if self.is_suspended.swap(false, Ordering::AcqRel) && self.active.load(Ordering::Acquire) {
+            unsafe {
+                libc::tcsetattr(self.fd, libc::TCSANOW, self.suspended.get());
+            }
+        }
+    }
+}
+
+/// Snapshots the state of a terminal on creation, and restores it when the
+/// guard is dropped, when the process exits or panics, or when the process
+/// is killed by a signal which is not otherwise handled.
+///
+/// When the process is suspended via `SIGTSTP`, the terminal is restored
+/// before stopping, and the state at the time of suspension is re-applied
+/// on `SIGCONT`.
+///
+/// This is intended for applications which put the terminal into raw mode.
+/// A termination signal does not restore the terminal whilst it is handled
+/// by a `ShutdownGuard` (or `ReloadGuard` for `SIGHUP`), since the process
+/// is then not about to exit. Termination signals which were ignored or
+/// handled by other code when the guard was created are not intercepted.
+pub struct TerminalGuard {
+    shared: Arc<Shared>,
+    guards: Vec<SignalHandlerGuard<'static>>,
+}
+
+impl TerminalGuard {
+    /// Snapshot the state of the terminal attached to standard input.
+    pub fn new() -> Result<Self, Error> {
+        Self::with_fd(libc::STDIN_FILENO)
+    }
+
+    /// Snapshot the state of the terminal referred to by `fd`.
+    pub fn with_fd(fd: RawFd) -> Result<Self, Error> {
+        let original = unsafe {
+            let mut termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut termios) != 0 {
+                return Err(Error::Os(io::Error::last_os_error()));
+            }
+            termios
+        };
+        let shared = Arc::new(Shared {
+            fd,
+            original,
+            suspended: UnsafeCell::new(original),
+            is_suspended: AtomicBool::new(false),
+            active: AtomicBool::new(true),
+        });
+
+        let mut res = Self {
+            shared,
+            guards: Vec::new(),
+        };
+        for &signums in TERMINATE_SIGNALS {
+            if is_default(signums[0]) {
+                res.add_handler(signums, Shared::terminate)?;
+            }
+        }
+        if disposition(libc::SIGTSTP) != libc::SIG_IGN {
+            res.add_handler(&[libc::SIGTSTP], Shared::suspend)?;
+            res.add_handler(&[libc::SIGCONT], Shared::resume)?;
+        }
+
+        register(&res.shared);
+        Ok(res)
+    }
+
+    fn add_handler(
+        &mut self,
+        signums: &'static [c_int],
+        f: fn(&Shared, c_int),
+    ) -> Result<(), Error> {
+        let shared = self.shared.clone();
+        let guard = unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                signums,
+                Arc::new(move |signum| {
+                    f(&shared, signum);
+                    // Allow the signal to be handled as it would otherwise
+                    false
+                }),
+            )?
+        };
+        self.guards.push(guard);
+        Ok(())
+    }
+
+    /// Restore the terminal to the state it was in when the guard was
+    /// created.
+    pub fn restore(&self) {
+        self.shared.restore();
+    }
+}
+
+impl std::fmt::Debug for TerminalGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TerminalGuard")
+            .field("fd", &self.shared.fd)
+            .finish()
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        self.guards.clear();
+        self.shared.restore();
+        self.shared.active.store(false, Ordering::Release);
+        let ptr = Arc::as_ptr(&self.shared);
+        GUARDS
+            .lock()
+            .retain(|item| item.strong_count() > 0 && item.as_ptr() != ptr);
+    }
+}
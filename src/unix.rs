@@ -1,84 +1,395 @@
-use std::cell::UnsafeCell;
+use std::cell::{Cell, UnsafeCell};
+use std::convert::TryFrom;
+use std::io;
 use std::mem::MaybeUninit;
+use std::os::unix::io::RawFd;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
-use signal_stack::SignalHandlerGuard;
+use signal_stack::{BlockSignalsGuard, Disposition, SigSet, SignalHandlerGuard, MAX_SIGNALS};
 
-use super::ShutdownType;
+use super::parent::ParentWatch;
+use super::{Error, ShutdownType};
 
-static mut NOTIFY_SEM: UnsafeCell<MaybeUninit<libc::sem_t>> =
-    UnsafeCell::new(MaybeUninit::uninit());
-static mut STOP_SEM: UnsafeCell<MaybeUninit<libc::sem_t>> = UnsafeCell::new(MaybeUninit::uninit());
+// Closes the file descriptor when dropped.
+pub(crate) struct Fd(pub(crate) RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+pub(crate) fn set_cloexec(fd: RawFd) {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 {
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+    }
+}
+
+// Create a pipe whose ends are both close-on-exec from the outset, so that
+// they cannot leak into a process spawned concurrently by another thread.
+// Returns the read and write ends, in that order.
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+pub(crate) fn cloexec_pipe() -> io::Result<(Fd, Fd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((Fd(fds[0]), Fd(fds[1])))
+}
+
+// These platforms lack `pipe2`, so there is an unavoidable window in which
+// the pipe may leak.
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn cloexec_pipe() -> io::Result<(Fd, Fd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let res = (Fd(fds[0]), Fd(fds[1]));
+    set_cloexec(res.0 .0);
+    set_cloexec(res.1 .0);
+    Ok(res)
+}
+
+// A semaphore which can be placed in a `static`, and so must be initialized
+// and destroyed manually.
+pub(crate) struct RawSemaphore(UnsafeCell<MaybeUninit<libc::sem_t>>);
+
+// The semaphore is only ever accessed via `libc` functions, which are thread-safe.
+unsafe impl Sync for RawSemaphore {}
+unsafe impl Send for RawSemaphore {}
+
+impl RawSemaphore {
+    pub(crate) const fn new() -> Self {
+        Self(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+    fn as_ptr(&self) -> *mut libc::sem_t {
+        self.0.get() as *mut libc::sem_t
+    }
+    // Safety: must be called exactly once, before any other method, and must
+    // not be moved afterwards.
+    pub(crate) unsafe fn init(&self) -> io::Result<()> {
+        if libc::sem_init(self.as_ptr(), 0, 0) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    // This is async-signal-safe.
+    pub(crate) fn post(&self) {
+        unsafe {
+            libc::sem_post(self.as_ptr());
+        }
+    }
+    pub(crate) fn wait(&self) {
+        // Retry if interrupted by a signal
+        while unsafe { libc::sem_wait(self.as_ptr()) } != 0
+            && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+        {}
+    }
+    // Safety: no other method may be called afterwards.
+    pub(crate) unsafe fn destroy(&self) {
+        libc::sem_destroy(self.as_ptr());
+    }
+}
+
+// A semaphore which is destroyed when dropped. It is boxed so that it does
+// not move once initialized.
+pub(crate) struct Semaphore(Box<RawSemaphore>);
+
+impl Semaphore {
+    pub(crate) fn new() -> io::Result<Self> {
+        let res = Box::new(RawSemaphore::new());
+        unsafe { res.init()? };
+        Ok(Self(res))
+    }
+    // This is async-signal-safe.
+    pub(crate) fn post(&self) {
+        self.0.post();
+    }
+    pub(crate) fn wait(&self) {
+        self.0.wait();
+    }
+}
+
+impl Drop for Semaphore {
+    fn drop(&mut self) {
+        unsafe { self.0.destroy() }
+    }
+}
+
+static NOTIFY_SEM: RawSemaphore = RawSemaphore::new();
+static STOP_SEM: RawSemaphore = RawSemaphore::new();
 static INT_COUNT: AtomicUsize = AtomicUsize::new(0);
 static TERM_COUNT: AtomicUsize = AtomicUsize::new(0);
+static PARENT_COUNT: AtomicUsize = AtomicUsize::new(0);
 static STOPPING: AtomicBool = AtomicBool::new(false);
 
+#[allow(clippy::declare_interior_mutable_const)]
+const NOT_INTERCEPTED: AtomicUsize = AtomicUsize::new(0);
+// How many of our handlers consume each signal, so that other handlers can
+// tell whether it will go on to take its default action.
+static INTERCEPTS: [AtomicUsize; MAX_SIGNALS] = [NOT_INTERCEPTED; MAX_SIGNALS];
+
+// Every signal number, so that a signal chosen at runtime can be handled by
+// a guard which requires a `'static` list of signals.
+static SIGNUMS: [libc::c_int; MAX_SIGNALS] = {
+    let mut res = [0; MAX_SIGNALS];
+    let mut i = 0;
+    while i < MAX_SIGNALS {
+        res[i] = i as libc::c_int;
+        i += 1;
+    }
+    res
+};
+
+// Returns a `'static` list containing only `signum`.
+pub(crate) fn single_signal(signum: libc::c_int) -> Result<&'static [libc::c_int], Error> {
+    usize::try_from(signum)
+        .ok()
+        .and_then(|index| SIGNUMS.get(index))
+        .map(std::slice::from_ref)
+        .ok_or(Error::Signal(signal_stack::Error::InvalidSignal(signum)))
+}
+
+// Marks a signal as being consumed by one of our handlers whilst it exists.
+pub(crate) struct Intercept(libc::c_int);
+
+impl Intercept {
+    pub(crate) fn new(signum: libc::c_int) -> Self {
+        if let Some(count) = INTERCEPTS.get(signum as usize) {
+            count.fetch_add(1, Ordering::SeqCst);
+        }
+        Self(signum)
+    }
+}
+
+impl Drop for Intercept {
+    fn drop(&mut self) {
+        if let Some(count) = INTERCEPTS.get(self.0 as usize) {
+            count.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+pub(crate) fn disposition(signum: libc::c_int) -> libc::sighandler_t {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(signum, std::ptr::null(), &mut action);
+        action.sa_sigaction
+    }
+}
+
+// Whether the signal takes its default action unless one of the handlers
+// installed via `signal-stack` intercepts it.
+pub(crate) fn is_default(signum: libc::c_int) -> bool {
+    disposition(signum) == libc::SIG_DFL
+        || signal_stack::installed()
+            .iter()
+            .any(|info| info.signum == signum && info.active && info.prev == [Disposition::Default])
+}
+
+// Returns `true` if one of our handlers currently consumes the signal. This
+// is async-signal-safe.
+pub(crate) fn is_intercepted(signum: libc::c_int) -> bool {
+    INTERCEPTS
+        .get(signum as usize)
+        .is_some_and(|count| count.load(Ordering::SeqCst) > 0)
+}
+
 fn load_and_reset(counter: &AtomicUsize) -> usize {
     let res = counter.load(Ordering::Relaxed);
     counter.fetch_sub(res, Ordering::Relaxed);
     res
 }
 
-fn sem_ptr(sem: &UnsafeCell<MaybeUninit<libc::sem_t>>) -> *mut libc::sem_t {
-    sem.get() as *mut libc::sem_t
-}
-
 fn background_thread() {
-    unsafe {
-        while !STOPPING.load(Ordering::Relaxed) {
-            libc::sem_wait(sem_ptr(&NOTIFY_SEM));
-            let int_count = load_and_reset(&INT_COUNT);
-            let term_count = load_and_reset(&TERM_COUNT);
-            for _ in 0..int_count {
-                super::handle(ShutdownType::Interrupt);
-            }
-            for _ in 0..term_count {
-                super::handle(ShutdownType::Terminate);
+    // This thread may have been spawned from a thread which blocks shutdown
+    // signals, but signals must be deliverable to at least one thread.
+    #[allow(unused_mut)]
+    let mut signals = SigSet::empty().with(libc::SIGINT).with(libc::SIGTERM);
+    #[cfg(target_os = "linux")]
+    signals
+        .insert(super::parent::signal())
+        .expect("Invalid signal number");
+    let _unblock = BlockSignalsGuard::unblock(&signals);
+    while !STOPPING.load(Ordering::Relaxed) {
+        NOTIFY_SEM.wait();
+        let received = Instant::now();
+        // If the last guard is dropped whilst requests are deferred, they are
+        // left queued. See `release_deferred`.
+        let stopping = || STOPPING.load(Ordering::Relaxed);
+        if !super::defer::wait(received, stopping) || stopping() {
+            break;
+        }
+        let int_count = load_and_reset(&INT_COUNT);
+        let term_count = load_and_reset(&TERM_COUNT);
+        let parent_count = load_and_reset(&PARENT_COUNT);
+        for (type_, count) in [
+            (ShutdownType::Interrupt, int_count),
+            (ShutdownType::Terminate, term_count),
+            (ShutdownType::ParentExited, parent_count),
+        ] {
+            if count > 0 {
+                super::handle(type_, count, received);
             }
         }
-        STOPPING.store(false, Ordering::Relaxed);
-        libc::sem_post(sem_ptr(&STOP_SEM));
     }
+    STOPPING.store(false, Ordering::Relaxed);
+    STOP_SEM.post();
 }
 
-fn signal_handler(signum: libc::c_int) -> bool {
-    match signum {
-        libc::SIGINT => &INT_COUNT,
-        libc::SIGTERM => &TERM_COUNT,
-        _ => unreachable!(),
+// Queue a shutdown request for the background thread. This is
+// async-signal-safe.
+fn counter(type_: ShutdownType) -> &'static AtomicUsize {
+    match type_ {
+        ShutdownType::Interrupt => &INT_COUNT,
+        ShutdownType::Terminate => &TERM_COUNT,
+        ShutdownType::ParentExited => &PARENT_COUNT,
     }
-    .fetch_add(1, Ordering::Relaxed);
-    unsafe {
-        libc::sem_post(sem_ptr(&NOTIFY_SEM));
+}
+
+pub(crate) fn notify(type_: ShutdownType) {
+    counter(type_).fetch_add(1, Ordering::Relaxed);
+    NOTIFY_SEM.post();
+}
+
+pub fn request(type_: ShutdownType) {
+    request_inner(type_, true);
+}
+
+// Like `request`, but never blocks waiting for shields to be released.
+pub(crate) fn request_nowait(type_: ShutdownType) {
+    request_inner(type_, false);
+}
+
+fn request_inner(type_: ShutdownType, may_wait: bool) {
+    if super::DISPATCHING.with(Cell::get) {
+        // Requested from within a shutdown handler (or a panic hook it
+        // triggered) on the background thread, which already holds the lock.
+        notify(type_);
+        return;
+    }
+    let guard = super::STATE.lock();
+    if guard.is_some() {
+        // The background thread is running, and cannot be stopped whilst
+        // we hold the lock.
+        notify(type_);
+    } else {
+        drop(guard);
+        let received = Instant::now();
+        // If this thread holds a shield, it would wait for itself forever.
+        let would_wait = !may_wait && super::defer::is_deferred();
+        if !would_wait && !super::defer::is_held() {
+            super::defer::wait(received, || false);
+            super::handle(type_, 1, received);
+            return;
+        }
+        let spawned = thread::Builder::new()
+            .name("grace-request".into())
+            .spawn(move || {
+                super::defer::wait(received, || false);
+                super::handle(type_, 1, received);
+            });
+        if spawned.is_err() {
+            // Delivered once the last shield is released
+            counter(type_).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+// Called when the last shield is released. Requests which were deferred
+// when the background thread stopped are delivered now, which causes the
+// process to exit unless a guard was installed in the meantime.
+pub(crate) fn release_deferred() {
+    if super::DISPATCHING.with(Cell::get) {
+        // The background thread is running, and delivers them itself
+        return;
     }
+    let guard = super::STATE.lock();
+    if guard.is_some() {
+        return;
+    }
+    drop(guard);
+    let received = Instant::now();
+    for (type_, counter) in [
+        (ShutdownType::Interrupt, &INT_COUNT),
+        (ShutdownType::Terminate, &TERM_COUNT),
+        (ShutdownType::ParentExited, &PARENT_COUNT),
+    ] {
+        let count = load_and_reset(counter);
+        if count > 0 {
+            super::handle(type_, count, received);
+        }
+    }
+}
+
+fn signal_handler(signum: libc::c_int) -> bool {
+    notify(match signum {
+        libc::SIGINT => ShutdownType::Interrupt,
+        libc::SIGTERM => ShutdownType::Terminate,
+        _ => unreachable!(),
+    });
     true
 }
 
-pub unsafe fn enter_outer() {
-    libc::sem_init(sem_ptr(&NOTIFY_SEM), 0, 0);
-    libc::sem_init(sem_ptr(&STOP_SEM), 0, 0);
-    thread::spawn(background_thread);
+pub unsafe fn enter_outer() -> Result<(), Error> {
+    NOTIFY_SEM.init().map_err(Error::Os)?;
+    if let Err(e) = STOP_SEM.init() {
+        NOTIFY_SEM.destroy();
+        return Err(Error::Os(e));
+    }
+    if let Err(e) = thread::Builder::new()
+        .name("grace".into())
+        .spawn(background_thread)
+    {
+        NOTIFY_SEM.destroy();
+        STOP_SEM.destroy();
+        return Err(Error::ThreadSpawn(e));
+    }
+    // Deliver any requests left queued by the previous background thread
+    if [&INT_COUNT, &TERM_COUNT, &PARENT_COUNT]
+        .iter()
+        .any(|counter| counter.load(Ordering::Relaxed) > 0)
+    {
+        NOTIFY_SEM.post();
+    }
+    Ok(())
 }
 
-pub type InternalGuard = SignalHandlerGuard<'static>;
+// The contents are only held so that they are dropped on `leave`.
+#[allow(dead_code)]
+pub enum InternalGuard {
+    Signal(SignalHandlerGuard<'static>, Intercept),
+    Parent(ParentWatch),
+}
 
-pub unsafe fn enter(type_: ShutdownType) -> InternalGuard {
-    SignalHandlerGuard::new_unsafe(
-        match type_ {
-            ShutdownType::Interrupt => &[libc::SIGINT],
-            ShutdownType::Terminate => &[libc::SIGTERM],
-        },
-        Arc::new(signal_handler),
-    )
+pub unsafe fn enter(type_: ShutdownType) -> Result<InternalGuard, Error> {
+    let signums: &'static [libc::c_int] = match type_ {
+        ShutdownType::Interrupt => &[libc::SIGINT],
+        ShutdownType::Terminate => &[libc::SIGTERM],
+        ShutdownType::ParentExited => return Ok(InternalGuard::Parent(ParentWatch::new()?)),
+    };
+    let intercept = Intercept::new(signums[0]);
+    Ok(InternalGuard::Signal(
+        SignalHandlerGuard::try_new_unsafe(signums, Arc::new(signal_handler))?,
+        intercept,
+    ))
 }
 pub unsafe fn leave(_guard: InternalGuard) {}
 
 pub unsafe fn leave_outer() {
     STOPPING.store(true, Ordering::Relaxed);
-    libc::sem_post(sem_ptr(&NOTIFY_SEM));
-    libc::sem_wait(sem_ptr(&STOP_SEM));
-    libc::sem_destroy(sem_ptr(&NOTIFY_SEM));
-    libc::sem_destroy(sem_ptr(&STOP_SEM));
+    NOTIFY_SEM.post();
+    super::defer::wake();
+    STOP_SEM.wait();
+    NOTIFY_SEM.destroy();
+    STOP_SEM.destroy();
 }
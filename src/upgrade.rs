@@ -0,0 +1,405 @@
+//! Zero-downtime restarts, by handing listening sockets over to a new
+//! instance of the program.
+//!
+//! When an upgrade is triggered (by default via `SIGUSR2`), the current
+//! executable is re-executed with the same arguments, and the listening
+//! sockets passed to `UpgradeGuard` are inherited by the new instance using
+//! the same convention as systemd socket activation (`LISTEN_FDS`). The new
+//! instance retrieves them via `listen_fds()`, and calls `ready()` once it
+//! is accepting connections. The old instance then shuts down gracefully,
+//! as though it had received a terminate request.
+//!
+//! If the new instance exits or fails to become ready in time, it is
+//! killed, and the old instance continues running as before.
+//!
+//! `LISTEN_PID` is not set for the new instance, since its process ID is
+//! not known until after it has been spawned.
+
+use std::env;
+use std::io;
+use std::num::ParseIntError;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use libc::c_int;
+use parking_lot::lock_api::RawMutex;
+use parking_lot::{Mutex, MutexGuard};
+use signal_stack::SignalHandlerGuard;
+
+use super::unix::{cloexec_pipe, set_cloexec, single_signal, Fd, Intercept};
+use super::worker::{Wakeup, Worker};
+use super::{request_shutdown, Error, ShutdownType};
+
+// The first inherited file descriptor, as with systemd socket activation
+const LISTEN_FDS_START: RawFd = 3;
+const READY_FD_VAR: &str = "GRACE_UPGRADE_FD";
+
+// The ready file descriptor, once it has been taken from the environment
+static READY_FD: Mutex<Option<Result<RawFd, ParseIntError>>> =
+    Mutex::const_new(RawMutex::INIT, None);
+
+// Take the ready file descriptor from the environment, if not already
+// taken, and mark it close-on-exec so that it is not leaked into any other
+// process spawned by this instance.
+fn ready_fd() -> MutexGuard<'static, Option<Result<RawFd, ParseIntError>>> {
+    let mut guard = READY_FD.lock();
+    if let Some(fd) = env::var_os(READY_FD_VAR) {
+        env::remove_var(READY_FD_VAR);
+        let fd = fd.to_string_lossy().parse::<RawFd>();
+        if let Ok(fd) = fd {
+            set_cloexec(fd);
+        }
+        *guard = Some(fd);
+    }
+    guard
+}
+
+/// Take ownership of the file descriptors inherited from a previous
+/// instance, or from systemd socket activation, along with their names
+/// (`LISTEN_FDNAMES`) if present.
+///
+/// The environment variables are removed, so subsequent calls return an
+/// empty list, and the file descriptors are marked close-on-exec, as is
+/// the file descriptor used by `ready()`.
+pub fn listen_fds_with_names() -> Vec<(RawFd, Option<String>)> {
+    drop(ready_fd());
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|s| s.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    let pid_matches = env::var("LISTEN_PID")
+        .ok()
+        .is_none_or(|pid| pid.parse::<u32>().ok() == Some(std::process::id()));
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDNAMES");
+
+    if !pid_matches || count <= 0 {
+        return Vec::new();
+    }
+    let mut names = names.split(':').map(|name| Some(name.to_owned()));
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            set_cloexec(fd);
+            (fd, names.next().flatten().filter(|name| !name.is_empty()))
+        })
+        .collect()
+}
+
+/// Take ownership of the file descriptors inherited from a previous
+/// instance. See `listen_fds_with_names`.
+pub fn listen_fds() -> Vec<RawFd> {
+    listen_fds_with_names()
+        .into_iter()
+        .map(|(fd, _)| fd)
+        .collect()
+}
+
+/// Returns `true` if this instance was started by an upgrade, and has not
+/// yet called `ready()`.
+pub fn is_upgrade() -> bool {
+    ready_fd().is_some()
+}
+
+/// Notify the previous instance that this instance is ready to take over,
+/// causing it to shut down.
+///
+/// Returns `false` if this instance was not started by an upgrade.
+pub fn ready() -> io::Result<bool> {
+    let fd = match ready_fd().take() {
+        Some(fd) => fd.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+        None => return Ok(false),
+    };
+    let res = unsafe { libc::write(fd, b"1".as_ptr() as *const _, 1) };
+    let err = io::Error::last_os_error();
+    unsafe {
+        libc::close(fd);
+    }
+    if res != 1 {
+        return Err(err);
+    }
+    Ok(true)
+}
+
+/// Options controlling how an upgrade is triggered and completed.
+#[derive(Clone, Debug)]
+pub struct UpgradeOptions {
+    signal: c_int,
+    timeout: Duration,
+    shutdown: ShutdownType,
+}
+
+impl Default for UpgradeOptions {
+    fn default() -> Self {
+        Self {
+            signal: libc::SIGUSR2,
+            timeout: Duration::from_secs(30),
+            shutdown: ShutdownType::Terminate,
+        }
+    }
+}
+
+impl UpgradeOptions {
+    /// Construct the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The signal which triggers an upgrade.
+    ///
+    /// Defaults to `SIGUSR2`.
+    pub fn signal(mut self, signum: c_int) -> Self {
+        self.signal = signum;
+        self
+    }
+
+    /// How long to wait for the new instance to call `ready()` before
+    /// rolling back.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The kind of shutdown request to deliver to this instance once the
+    /// new instance is ready.
+    ///
+    /// Defaults to `ShutdownType::Terminate`.
+    pub fn shutdown(mut self, type_: ShutdownType) -> Self {
+        self.shutdown = type_;
+        self
+    }
+}
+
+type Callback = Box<dyn FnMut(&io::Result<u32>) + Send>;
+
+struct Shared {
+    fds: Vec<RawFd>,
+    options: UpgradeOptions,
+    wakeup: Arc<Wakeup>,
+    upgraded: AtomicBool,
+    callback: Mutex<Callback>,
+    // Serializes upgrades triggered by signal and by `UpgradeGuard::upgrade`
+    lock: Mutex<()>,
+}
+
+fn cvt(res: c_int) -> io::Result<c_int> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+// Duplicate a file descriptor above the range which will be inherited, so
+// that it cannot be clobbered when the inherited descriptors are arranged.
+fn dup_high(fd: RawFd, min: RawFd) -> io::Result<Fd> {
+    cvt(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, min) }).map(Fd)
+}
+
+// Wait for the new instance to write to the readiness pipe.
+fn wait_ready(fd: RawFd, child: &mut Child, timeout: Duration) -> io::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "new instance did not become ready in time",
+            ));
+        }
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = remaining.as_millis().min(c_int::MAX as u128) as c_int;
+        match cvt(unsafe { libc::poll(&mut pollfd, 1, millis.max(1)) }) {
+            Ok(0) => {}
+            Ok(_) => {
+                let mut buf = [0u8; 1];
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, 1) };
+                if n == 1 {
+                    return Ok(());
+                }
+                let msg = match child.try_wait()? {
+                    Some(status) => {
+                        format!("new instance exited before becoming ready: {}", status)
+                    }
+                    None => "new instance closed the readiness pipe".into(),
+                };
+                return Err(io::Error::other(msg));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Shared {
+    fn spawn(&self) -> io::Result<(Child, Fd)> {
+        let count = self.fds.len() as RawFd;
+        let ready_target = LISTEN_FDS_START + count;
+        let min = ready_target + 1;
+
+        let (read, original_write) = cloexec_pipe()?;
+        // Only the copy above the inherited range is needed
+        let write = dup_high(original_write.0, min)?;
+        drop(original_write);
+        let sources = self
+            .fds
+            .iter()
+            .map(|&fd| dup_high(fd, min))
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let mut command = Command::new(env::current_exe()?);
+        command
+            .args(env::args_os().skip(1))
+            .env("LISTEN_FDS", count.to_string())
+            .env(READY_FD_VAR, ready_target.to_string())
+            .env_remove("LISTEN_PID")
+            .env_remove("LISTEN_FDNAMES");
+        let raw_sources: Vec<RawFd> = sources.iter().map(|p| p.0).collect();
+        let raw_write = write.0;
+        unsafe {
+            command.pre_exec(move || {
+                // `dup2` clears the close-on-exec flag on the new descriptor
+                for (i, &fd) in raw_sources.iter().enumerate() {
+                    cvt(libc::dup2(fd, LISTEN_FDS_START + i as RawFd))?;
+                }
+                cvt(libc::dup2(raw_write, ready_target))?;
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        Ok((child, read))
+    }
+
+    fn upgrade(&self) -> io::Result<u32> {
+        let _lock = self.lock.lock();
+        if self.upgraded.load(Ordering::Relaxed) {
+            return Err(io::Error::other("an upgrade has already completed"));
+        }
+
+        let (mut child, read) = self.spawn()?;
+        if let Err(e) = wait_ready(read.0, &mut child, self.options.timeout) {
+            // Roll back, leaving this instance running
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+        self.upgraded.store(true, Ordering::Relaxed);
+
+        #[cfg(feature = "systemd")]
+        let _ = super::systemd::notify(&format!("MAINPID={}", child.id()));
+        Ok(child.id())
+    }
+
+    fn run(&self) {
+        while self.wakeup.wait() {
+            let res = self.upgrade();
+            (self.callback.lock())(&res);
+            if res.is_ok() {
+                request_shutdown(self.options.shutdown);
+            }
+        }
+    }
+}
+
+/// Re-executes the program when an upgrade is requested, handing over a
+/// set of listening sockets to the new instance, for as long as the guard
+/// exists. See the module documentation for details.
+///
+/// The file descriptors must remain open whilst the guard exists.
+pub struct UpgradeGuard {
+    shared: Arc<Shared>,
+    worker: Option<Worker>,
+    signal: Option<SignalHandlerGuard<'static>>,
+    intercept: Option<Intercept>,
+}
+
+impl UpgradeGuard {
+    /// Hand over `fds` when `SIGUSR2` is received.
+    pub fn new(fds: &[RawFd]) -> Result<Self, Error> {
+        Self::with_options(fds, &UpgradeOptions::new(), |_| {})
+    }
+
+    /// Hand over `fds` according to `options`, calling `callback` with the
+    /// process ID of the new instance, or the reason the upgrade failed.
+    ///
+    /// On success, the callback is called before the shutdown request is
+    /// delivered to this instance.
+    pub fn with_options<F: FnMut(&io::Result<u32>) + Send + 'static>(
+        fds: &[RawFd],
+        options: &UpgradeOptions,
+        callback: F,
+    ) -> Result<Self, Error> {
+        let shared = Arc::new(Shared {
+            fds: fds.to_vec(),
+            options: options.clone(),
+            wakeup: Wakeup::new()?,
+            upgraded: AtomicBool::new(false),
+            callback: Mutex::new(Box::new(callback)),
+            lock: Mutex::new(()),
+        });
+        let mut res = Self {
+            shared,
+            worker: None,
+            signal: None,
+            intercept: None,
+        };
+
+        let thread_shared = res.shared.clone();
+        res.worker = Some(Worker::spawn(
+            "grace-upgrade",
+            &res.shared.wakeup,
+            move || thread_shared.run(),
+        )?);
+
+        let handler_shared = res.shared.clone();
+        res.intercept = Some(Intercept::new(options.signal));
+        res.signal = Some(unsafe {
+            SignalHandlerGuard::try_new_unsafe(
+                single_signal(options.signal)?,
+                Arc::new(move |_| {
+                    handler_shared.wakeup.wake();
+                    true
+                }),
+            )?
+        });
+
+        Ok(res)
+    }
+
+    /// Perform an upgrade immediately, returning the process ID of the new
+    /// instance once it is ready.
+    ///
+    /// Unlike an upgrade triggered by a signal, no shutdown request is
+    /// delivered to this instance on success.
+    pub fn upgrade(&self) -> io::Result<u32> {
+        self.shared.upgrade()
+    }
+}
+
+impl std::fmt::Debug for UpgradeGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpgradeGuard")
+            .field("fds", &self.shared.fds)
+            .field("options", &self.shared.options)
+            .finish()
+    }
+}
+
+impl Drop for UpgradeGuard {
+    fn drop(&mut self) {
+        self.signal.take();
+        self.intercept.take();
+        self.worker.take();
+    }
+}
@@ -5,17 +5,34 @@ use winapi::um::wincon::{
     PHANDLER_ROUTINE,
 };
 
-use super::ShutdownType;
+use std::cell::Cell;
+use std::io;
+use std::thread;
+use std::time::Instant;
 
-pub unsafe fn enter_outer() {}
+use super::{Error, ShutdownType};
+
+pub unsafe fn enter_outer() -> Result<(), Error> {
+    Ok(())
+}
 pub unsafe fn leave_outer() {}
 
+// Shields are waited for on the thread which received the request, so
+// nothing is ever left queued.
+pub(crate) fn release_deferred() {}
+
+fn dispatch(type_: ShutdownType) {
+    let received = Instant::now();
+    super::defer::wait(received, || false);
+    super::handle(type_, 1, received);
+}
+
 pub type InternalGuard = PHANDLER_ROUTINE;
 
 unsafe extern "system" fn handle_interrupt(ctrl_type: DWORD) -> BOOL {
     match ctrl_type {
         CTRL_C_EVENT | CTRL_BREAK_EVENT => {
-            super::handle(ShutdownType::Interrupt);
+            dispatch(ShutdownType::Interrupt);
             1
         }
         _ => 0,
@@ -25,20 +42,52 @@ unsafe extern "system" fn handle_interrupt(ctrl_type: DWORD) -> BOOL {
 unsafe extern "system" fn handle_terminate(ctrl_type: DWORD) -> BOOL {
     match ctrl_type {
         CTRL_CLOSE_EVENT | CTRL_LOGOFF_EVENT | CTRL_SHUTDOWN_EVENT => {
-            super::handle(ShutdownType::Terminate);
+            dispatch(ShutdownType::Terminate);
             1
         }
         _ => 0,
     }
 }
 
-pub unsafe fn enter(type_: ShutdownType) -> InternalGuard {
+pub fn request(type_: ShutdownType) {
+    request_inner(type_, true);
+}
+
+// Like `request`, but never blocks waiting for shields to be released.
+pub(crate) fn request_nowait(type_: ShutdownType) {
+    request_inner(type_, false);
+}
+
+fn request_inner(type_: ShutdownType, may_wait: bool) {
+    // From within a shutdown handler, which holds the lock, or from a thread
+    // which holds a shield, the request cannot be delivered on this thread.
+    if super::DISPATCHING.with(Cell::get)
+        || super::defer::is_held()
+        || (!may_wait && super::defer::is_deferred())
+    {
+        let _ = thread::Builder::new()
+            .name("grace-request".into())
+            .spawn(move || dispatch(type_));
+    } else {
+        dispatch(type_);
+    }
+}
+
+pub unsafe fn enter(type_: ShutdownType) -> Result<InternalGuard, Error> {
     let handler = Some(match type_ {
         ShutdownType::Interrupt => handle_interrupt,
         ShutdownType::Terminate => handle_terminate,
+        ShutdownType::ParentExited => {
+            return Err(Error::Os(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "parent process exit detection is not supported on windows",
+            )))
+        }
     });
-    SetConsoleCtrlHandler(handler, 1);
-    handler
+    if SetConsoleCtrlHandler(handler, 1) == 0 {
+        return Err(Error::Os(io::Error::last_os_error()));
+    }
+    Ok(handler)
 }
 pub unsafe fn leave(guard: InternalGuard) {
     SetConsoleCtrlHandler(guard, 0);
@@ -0,0 +1,159 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use parking_lot::lock_api::RawMutex;
+use parking_lot::Mutex;
+
+use super::unix::Semaphore;
+use super::Error;
+
+// Wakes a background thread, typically from a signal handler.
+pub(crate) struct Wakeup {
+    sem: Semaphore,
+    stopping: AtomicBool,
+}
+
+impl Wakeup {
+    pub(crate) fn new() -> Result<Arc<Self>, Error> {
+        Ok(Arc::new(Self {
+            sem: Semaphore::new().map_err(Error::Os)?,
+            stopping: AtomicBool::new(false),
+        }))
+    }
+    // This is async-signal-safe.
+    pub(crate) fn wake(&self) {
+        self.sem.post();
+    }
+    // Blocks until woken. Returns `false` once the thread should exit.
+    pub(crate) fn wait(&self) -> bool {
+        self.sem.wait();
+        !self.is_stopping()
+    }
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+}
+
+type Stop = Box<dyn FnOnce() + Send>;
+
+// A named background thread, which is stopped and joined when dropped.
+pub(crate) struct Worker {
+    stop: Option<Stop>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    // Spawn a thread which should exit once `wakeup` reports it is stopping.
+    pub(crate) fn spawn<F: FnOnce() + Send + 'static>(
+        name: &str,
+        wakeup: &Arc<Wakeup>,
+        f: F,
+    ) -> Result<Self, Error> {
+        let wakeup = wakeup.clone();
+        Self::with_stop(
+            name,
+            move || {
+                wakeup.stopping.store(true, Ordering::SeqCst);
+                wakeup.wake();
+            },
+            f,
+        )
+    }
+
+    // Spawn a thread which should exit once `stop` has been called.
+    pub(crate) fn with_stop<S, F>(name: &str, stop: S, f: F) -> Result<Self, Error>
+    where
+        S: FnOnce() + Send + 'static,
+        F: FnOnce() + Send + 'static,
+    {
+        let thread = thread::Builder::new()
+            .name(name.into())
+            .spawn(f)
+            .map_err(Error::ThreadSpawn)?;
+        Ok(Self {
+            stop: Some(Box::new(stop)),
+            thread: Some(thread),
+        })
+    }
+
+    // Spawn a thread which calls `f` every `interval`, until it is stopped
+    // or until `f` returns `false`.
+    pub(crate) fn every<F: FnMut() -> bool + Send + 'static>(
+        name: &str,
+        interval: Duration,
+        mut f: F,
+    ) -> Result<Self, Error> {
+        let (tx, rx) = mpsc::channel::<()>();
+        Self::with_stop(
+            name,
+            move || drop(tx),
+            move || {
+                while rx.recv_timeout(interval) == Err(RecvTimeoutError::Timeout) && f() {}
+            },
+        )
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+        if let Some(thread) = self.thread.take() {
+            // In case the thread is parked rather than waiting to be woken
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+// Process-wide state shared by every guard of one kind, which is created
+// along with the first guard and destroyed along with the last.
+pub(crate) struct Registry<S, T: ?Sized> {
+    inner: Mutex<Option<(S, Vec<Arc<T>>)>>,
+}
+
+impl<S, T: ?Sized> Registry<S, T> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            inner: Mutex::const_new(RawMutex::INIT, None),
+        }
+    }
+    pub(crate) fn add<F: FnOnce() -> Result<S, Error>>(
+        &self,
+        item: Arc<T>,
+        init: F,
+    ) -> Result<(), Error> {
+        let mut guard = self.inner.lock();
+        if guard.is_none() {
+            *guard = Some((init()?, Vec::new()));
+        }
+        guard.as_mut().unwrap().1.push(item);
+        Ok(())
+    }
+    pub(crate) fn remove(&self, item: &Arc<T>) {
+        let mut guard = self.inner.lock();
+        if let Some((_, items)) = guard.as_mut() {
+            if let Some(index) = items.iter().rposition(|other| Arc::ptr_eq(other, item)) {
+                items.remove(index);
+            }
+            if items.is_empty() {
+                // Destroy the state without holding the lock, since its
+                // background thread may be waiting to acquire it.
+                let state = guard.take();
+                drop(guard);
+                drop(state);
+            }
+        }
+    }
+    // Returns the registered items, in the order they were added.
+    pub(crate) fn items(&self) -> Vec<Arc<T>> {
+        self.inner
+            .lock()
+            .as_ref()
+            .map_or_else(Vec::new, |(_, items)| items.clone())
+    }
+}
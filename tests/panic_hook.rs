@@ -0,0 +1,79 @@
+use std::env;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use grace::{defer_shutdown, shutdown_on_panic, ShutdownType};
+
+const CHILD_VAR: &str = "GRACE_TEST_PANIC_CHILD";
+
+// Runs in a child process, since the process exits once the shutdown
+// request is delivered with no handler installed.
+fn panic_in_child(own_shield: bool) {
+    shutdown_on_panic(ShutdownType::Terminate);
+    // Another thread is also inside a critical section
+    let other = defer_shutdown();
+    let _ = thread::spawn(move || {
+        let _shield = if own_shield {
+            Some(defer_shutdown())
+        } else {
+            None
+        };
+        panic!("panicked");
+    })
+    .join();
+    println!("joined");
+    drop(other);
+    thread::sleep(Duration::from_secs(10));
+}
+
+fn run_child(test: &str) {
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["--exact", test, "--nocapture"])
+        .env(CHILD_VAR, "1")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if Instant::now() > deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            panic!("the panic hook blocked waiting for a shield");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert!(output.contains("joined"), "{:?}", output);
+    // The request is delivered once every shield is released
+    assert_eq!(status.code(), Some(3));
+}
+
+#[test]
+fn panic_inside_shield() {
+    if env::var_os(CHILD_VAR).is_some() {
+        panic_in_child(true);
+    } else {
+        run_child("panic_inside_shield");
+    }
+}
+
+#[test]
+fn panic_whilst_shielded_elsewhere() {
+    if env::var_os(CHILD_VAR).is_some() {
+        panic_in_child(false);
+    } else {
+        run_child("panic_whilst_shielded_elsewhere");
+    }
+}
@@ -0,0 +1,83 @@
+#![cfg(all(unix, feature = "systemd"))]
+
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use grace::systemd::{self, ExtendTimeoutGuard, WatchdogGuard};
+use grace::{request_shutdown, ShutdownGuard, ShutdownType};
+
+fn recv(socket: &UnixDatagram) -> String {
+    let mut buf = [0u8; 256];
+    let len = socket.recv(&mut buf).expect("No notification received");
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+// The notification state is process-wide, so everything is checked in
+// order from a single test.
+#[test]
+fn notifications() {
+    let dir = env::temp_dir().join(format!("grace-systemd-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notify");
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    env::set_var("NOTIFY_SOCKET", &path);
+
+    assert!(systemd::ready().unwrap());
+    assert_eq!(recv(&socket), "READY=1");
+
+    let extend = ExtendTimeoutGuard::new(Duration::from_secs(10)).unwrap();
+    assert_eq!(recv(&socket), "EXTEND_TIMEOUT_USEC=10000000");
+    drop(extend);
+
+    let watchdog = WatchdogGuard::with_interval(Duration::from_millis(20)).unwrap();
+    assert_eq!(recv(&socket), "WATCHDOG=1");
+    assert_eq!(recv(&socket), "WATCHDOG=1");
+
+    let (guard, rx) = ShutdownGuard::new_channel(&[ShutdownType::Terminate]);
+    request_shutdown(ShutdownType::Terminate);
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(5)),
+        Ok(ShutdownType::Terminate)
+    );
+
+    // Watchdog pings may precede `STOPPING=1`, but never follow it. The
+    // timeout is extended whilst the handler runs.
+    let mut received = Vec::new();
+    socket
+        .set_read_timeout(Some(Duration::from_millis(200)))
+        .unwrap();
+    let mut buf = [0u8; 256];
+    while let Ok(len) = socket.recv(&mut buf) {
+        received.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+    }
+    let stopping = received
+        .iter()
+        .position(|item| item == "STOPPING=1")
+        .expect("STOPPING=1 was not sent");
+    assert_eq!(
+        received[stopping + 1..],
+        ["EXTEND_TIMEOUT_USEC=10000000"],
+        "{:?}",
+        received
+    );
+
+    // Only the first shutdown request is reported
+    request_shutdown(ShutdownType::Terminate);
+    assert_eq!(
+        rx.recv_timeout(Duration::from_secs(5)),
+        Ok(ShutdownType::Terminate)
+    );
+    assert_eq!(recv(&socket), "EXTEND_TIMEOUT_USEC=10000000");
+    assert!(socket.recv(&mut buf).is_err());
+
+    drop(guard);
+    drop(watchdog);
+    env::remove_var("NOTIFY_SOCKET");
+    let _ = fs::remove_dir_all(&dir);
+}